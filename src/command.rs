@@ -2,10 +2,10 @@
 
 use core::ptr::{slice_from_raw_parts, slice_from_raw_parts_mut};
 
-use alloc::vec::Vec;
+use alloc::{string::String, vec::Vec};
 use log::debug;
 
-use crate::queue::CommandSet;
+use crate::{queue::CommandSet, sgl::SglSupport};
 
 #[repr(transparent)]
 pub struct Opcode(u8);
@@ -30,6 +30,8 @@ impl Opcode {
     pub const GET_FEATURES: Self = Self::new(0b0, 0b10, 0b10);
     pub const ASYNCHRONOUS_EVENT_REQUEST: Self = Self::new(0b0, 0b11, 0b0);
     pub const NAMESPACE_MANAGEMENT: Self = Self::new(0b0, 0b11, 0b1);
+    pub const FORMAT_NVM: Self = Self::new(0b1, 0b0, 0b0);
+    pub const SANITIZE: Self = Self::new(0b1, 0b1, 0b0);
     pub const FIRMWARE_COMMIT: Self = Self::new(0b1, 0b100, 0b0);
     pub const FIRMWARE_IMAGE_DOWNLOAD: Self = Self::new(0b1, 0b100, 0b1);
     pub const DEVICE_SELF_TEST: Self = Self::new(0b1, 0b101, 0b0);
@@ -48,8 +50,14 @@ impl Opcode {
 }
 
 pub enum Feature {
-    NumberOfQueues { nsq: u32, ncq: u32 },
-    InterruptVectorConfiguration {},
+    NumberOfQueues {
+        nsq: u32,
+        ncq: u32,
+    },
+    InterruptVectorConfiguration {
+        vector: u16,
+        coalescing_disable: bool,
+    },
 }
 
 impl Feature {
@@ -61,14 +69,31 @@ impl Feature {
     }
 }
 
+/// One kind of Identify data, selected by its CNS value. Pass it to
+/// [`crate::Nvme::get_identfy`].
+///
+/// The opcode and the CNS byte of CDW10 are filled in when the command is
+/// issued. Everything else set in [`Identify::command_set_mut`] is kept,
+/// such as NSID, CNTID in CDW10 bits 31:16 and CSI in CDW11 bits 31:24.
 pub trait Identify {
     const CNS: u32;
     type Output;
 
     fn command_set_mut(&mut self) -> &mut CommandSet;
+    /// Decode the 4 KiB data returned by the controller.
     fn parse(&self, data: &[u8]) -> Self::Output;
 }
 
+// CDW10 bits 31:16
+fn cdw10_cntid(cntid: u16) -> u32 {
+    (cntid as u32) << 16
+}
+
+// CDW11 bits 31:24
+fn cdw11_csi(csi: u8) -> u32 {
+    (csi as u32) << 24
+}
+
 pub struct IdentifyNamespaceDataStructure {
     command_set: CommandSet,
 }
@@ -89,41 +114,7 @@ impl Identify for IdentifyNamespaceDataStructure {
     type Output = Option<NamespaceDataStructure>;
 
     fn parse(&self, data: &[u8]) -> Self::Output {
-        let raw = unsafe { &*slice_from_raw_parts(data.as_ptr() as *const u32, data.len() / 4) };
-        unsafe {
-            if raw[0] == 0 {
-                return None;
-            }
-            let number_of_lba_formats = data.as_ptr().add(25).read_volatile();
-            let formatted_lba_size_field = data.as_ptr().add(26).read_volatile();
-            let has_metadata = (formatted_lba_size_field >> 4) & 1 == 1;
-
-            let lba_fmt_list = data.as_ptr().add(128) as *const LBAFormatDataStructure;
-
-            let lba_size_idx = (formatted_lba_size_field & 0b1111) as usize;
-
-            let lba_fmt = if lba_size_idx > 0 {
-                lba_fmt_list.add(lba_size_idx).read_volatile()
-            } else {
-                LBAFormatDataStructure {
-                    metadata_size: 0,
-                    lba_data_size: 9,
-                    other: 0,
-                }
-            };
-
-            Some(NamespaceDataStructure {
-                namespace_size: raw[0],
-                namespcae_capacity: raw[1],
-                namespace_nused: raw[2],
-                lba_size: 2u32.pow(lba_fmt.lba_data_size as u32),
-                metadata_size: if has_metadata {
-                    data[27] as _
-                } else {
-                    lba_fmt.metadata_size as _
-                },
-            })
-        }
+        parse_namespace(data)
     }
 
     fn command_set_mut(&mut self) -> &mut CommandSet {
@@ -131,6 +122,62 @@ impl Identify for IdentifyNamespaceDataStructure {
     }
 }
 
+fn parse_namespace(data: &[u8]) -> Option<NamespaceDataStructure> {
+    let raw = unsafe { (data.as_ptr() as *const NamespaceData).read_volatile() };
+    // an inactive namespace reads as all zeroes
+    if raw.nsze == 0 {
+        return None;
+    }
+
+    // NLBAF is 0's based, the structure holds at most 64 formats
+    let lba_formats = raw.lbaf[..=(raw.nlbaf as usize).min(63)]
+        .iter()
+        .map(|f| LbaFormat {
+            metadata_size: f.ms,
+            lba_data_size: f.lbads,
+            relative_performance: f.rp & 0b11,
+        })
+        .collect();
+
+    Some(NamespaceDataStructure {
+        namespace_size: raw.nsze,
+        namespace_capacity: raw.ncap,
+        namespace_utilization: raw.nuse,
+        nsfeat: raw.nsfeat,
+        lba_formats,
+        // bits 3:0 hold the low and bits 6:5 the high part of the index
+        formatted_lba_index: (raw.flbas & 0xf | (raw.flbas >> 5 & 0b11) << 4) as usize,
+        metadata_extended: raw.flbas & (1 << 4) != 0,
+        metadata_capabilities: raw.mc,
+        dpc: raw.dpc,
+        dps: raw.dps,
+        nmic: raw.nmic,
+        rescap: raw.rescap,
+        fpi: raw.fpi,
+        dlfeat: raw.dlfeat,
+        nawun: raw.nawun,
+        nawupf: raw.nawupf,
+        nacwu: raw.nacwu,
+        nabsn: raw.nabsn,
+        nabo: raw.nabo,
+        nabspf: raw.nabspf,
+        noiob: raw.noiob,
+        nvm_capacity: u128::from_le_bytes(raw.nvmcap),
+        npwg: raw.npwg,
+        npwa: raw.npwa,
+        npdg: raw.npdg,
+        npda: raw.npda,
+        nows: raw.nows,
+        ana_group_id: raw.anagrpid,
+        nsattr: raw.nsattr,
+        nvm_set_id: raw.nvmsetid,
+        endurance_group_id: raw.endgid,
+        nguid: raw.nguid,
+        eui64: raw.eui64,
+    })
+}
+
+#[derive(Default)]
 pub struct IdentifyActiveNamespaceList {
     command_set: CommandSet,
 }
@@ -148,18 +195,50 @@ impl Identify for IdentifyActiveNamespaceList {
     type Output = Vec<u32>;
 
     fn parse(&self, data: &[u8]) -> Self::Output {
-        let mut id_list = Vec::new();
+        parse_namespace_list(data)
+    }
+
+    fn command_set_mut(&mut self) -> &mut CommandSet {
+        &mut self.command_set
+    }
+}
 
-        let raw = unsafe { &*slice_from_raw_parts(data.as_ptr() as *const u32, data.len() / 4) };
+// up to 1024 namespace ids, ending at the first zero
+fn parse_namespace_list(data: &[u8]) -> Vec<u32> {
+    let raw = unsafe { &*slice_from_raw_parts(data.as_ptr() as *const u32, data.len() / 4) };
+    raw.iter().copied().take_while(|&id| id != 0).collect()
+}
 
-        for id in raw {
-            if *id == 0 {
-                break;
-            }
-            id_list.push(*id);
-        }
+// number of entries, then up to 2047 controller ids
+fn parse_controller_list(data: &[u8]) -> Vec<u16> {
+    let raw = unsafe { &*slice_from_raw_parts(data.as_ptr() as *const u16, data.len() / 2) };
+    let count = (raw[0] as usize).min(raw.len() - 1);
+    raw[1..=count].to_vec()
+}
+
+/// Allocated namespace ids greater than `start_nsid` (CNS 10h), including
+/// those not attached to any controller.
+pub struct IdentifyAllocatedNamespaceList {
+    command_set: CommandSet,
+}
 
-        id_list
+impl IdentifyAllocatedNamespaceList {
+    pub fn new(start_nsid: u32) -> Self {
+        let command_set = CommandSet {
+            nsid: start_nsid,
+            ..Default::default()
+        };
+        Self { command_set }
+    }
+}
+
+impl Identify for IdentifyAllocatedNamespaceList {
+    const CNS: u32 = 0x10;
+
+    type Output = Vec<u32>;
+
+    fn parse(&self, data: &[u8]) -> Self::Output {
+        parse_namespace_list(data)
     }
 
     fn command_set_mut(&mut self) -> &mut CommandSet {
@@ -167,29 +246,382 @@ impl Identify for IdentifyActiveNamespaceList {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct NamespaceDataStructure {
-    pub namespace_size: u32,
-    pub namespcae_capacity: u32,
-    pub namespace_nused: u32,
-    pub lba_size: u32,
-    pub metadata_size: u32,
+/// Identify Namespace data of an allocated namespace (CNS 11h), whether or
+/// not it is attached.
+pub struct IdentifyAllocatedNamespace {
+    command_set: CommandSet,
+}
+
+impl IdentifyAllocatedNamespace {
+    pub fn new(nsid: u32) -> Self {
+        let command_set = CommandSet {
+            nsid,
+            ..Default::default()
+        };
+        Self { command_set }
+    }
+}
+
+impl Identify for IdentifyAllocatedNamespace {
+    const CNS: u32 = 0x11;
+
+    type Output = Option<NamespaceDataStructure>;
+
+    fn parse(&self, data: &[u8]) -> Self::Output {
+        parse_namespace(data)
+    }
+
+    fn command_set_mut(&mut self) -> &mut CommandSet {
+        &mut self.command_set
+    }
+}
+
+/// Controllers attached to `nsid` with an id of at least `start_cntid`
+/// (CNS 12h).
+pub struct IdentifyNamespaceControllerList {
+    command_set: CommandSet,
+}
+
+impl IdentifyNamespaceControllerList {
+    pub fn new(nsid: u32, start_cntid: u16) -> Self {
+        let command_set = CommandSet {
+            nsid,
+            cdw10: cdw10_cntid(start_cntid),
+            ..Default::default()
+        };
+        Self { command_set }
+    }
+}
+
+impl Identify for IdentifyNamespaceControllerList {
+    const CNS: u32 = 0x12;
+
+    type Output = Vec<u16>;
+
+    fn parse(&self, data: &[u8]) -> Self::Output {
+        parse_controller_list(data)
+    }
+
+    fn command_set_mut(&mut self) -> &mut CommandSet {
+        &mut self.command_set
+    }
+}
+
+/// Controllers in the NVM subsystem with an id of at least `start_cntid`
+/// (CNS 13h).
+pub struct IdentifyControllerList {
+    command_set: CommandSet,
+}
+
+impl IdentifyControllerList {
+    pub fn new(start_cntid: u16) -> Self {
+        let command_set = CommandSet {
+            cdw10: cdw10_cntid(start_cntid),
+            ..Default::default()
+        };
+        Self { command_set }
+    }
+}
+
+impl Identify for IdentifyControllerList {
+    const CNS: u32 = 0x13;
+
+    type Output = Vec<u16>;
+
+    fn parse(&self, data: &[u8]) -> Self::Output {
+        parse_controller_list(data)
+    }
+
+    fn command_set_mut(&mut self) -> &mut CommandSet {
+        &mut self.command_set
+    }
+}
+
+/// I/O Command Set specific Identify Controller data (CNS 06h) for the
+/// command set `csi`. Its layout depends on the command set, so the raw
+/// 4 KiB are returned.
+pub struct IdentifyIoCommandSetController {
+    command_set: CommandSet,
+}
+
+impl IdentifyIoCommandSetController {
+    pub fn new(csi: u8) -> Self {
+        let command_set = CommandSet {
+            cdw11: cdw11_csi(csi),
+            ..Default::default()
+        };
+        Self { command_set }
+    }
+}
+
+impl Identify for IdentifyIoCommandSetController {
+    const CNS: u32 = 0x06;
+
+    type Output = Vec<u8>;
+
+    fn parse(&self, data: &[u8]) -> Self::Output {
+        data.to_vec()
+    }
+
+    fn command_set_mut(&mut self) -> &mut CommandSet {
+        &mut self.command_set
+    }
 }
 
+/// I/O Command Set combinations supported by controller `cntid` (CNS 1Ch).
+/// Each entry is a bit vector of command sets indexed by CSI, its position
+/// is the index used to select the combination.
+pub struct IdentifyIoCommandSet {
+    command_set: CommandSet,
+}
+
+impl IdentifyIoCommandSet {
+    pub fn new(cntid: u16) -> Self {
+        let command_set = CommandSet {
+            cdw10: cdw10_cntid(cntid),
+            ..Default::default()
+        };
+        Self { command_set }
+    }
+}
+
+impl Identify for IdentifyIoCommandSet {
+    const CNS: u32 = 0x1C;
+
+    type Output = Vec<u64>;
+
+    fn parse(&self, data: &[u8]) -> Self::Output {
+        let raw = unsafe { &*slice_from_raw_parts(data.as_ptr() as *const u64, data.len() / 8) };
+        let len = raw.iter().rposition(|&v| v != 0).map_or(0, |i| i + 1);
+        raw[..len].to_vec()
+    }
+
+    fn command_set_mut(&mut self) -> &mut CommandSet {
+        &mut self.command_set
+    }
+}
+
+/// Identify Namespace data structure (CNS 00h), as laid out by the spec.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct NamespaceData {
+    pub nsze: u64,
+    pub ncap: u64,
+    pub nuse: u64,
+    pub nsfeat: u8,
+    pub nlbaf: u8,
+    pub flbas: u8,
+    pub mc: u8,
+    pub dpc: u8,
+    pub dps: u8,
+    pub nmic: u8,
+    pub rescap: u8,
+    pub fpi: u8,
+    pub dlfeat: u8,
+    pub nawun: u16,
+    pub nawupf: u16,
+    pub nacwu: u16,
+    pub nabsn: u16,
+    pub nabo: u16,
+    pub nabspf: u16,
+    pub noiob: u16,
+    pub nvmcap: [u8; 16],
+    pub npwg: u16,
+    pub npwa: u16,
+    pub npdg: u16,
+    pub npda: u16,
+    pub nows: u16,
+    pub rsv74: [u8; 18],
+    pub anagrpid: u32,
+    pub rsv96: [u8; 3],
+    pub nsattr: u8,
+    pub nvmsetid: u16,
+    pub endgid: u16,
+    pub nguid: [u8; 16],
+    pub eui64: [u8; 8],
+    pub lbaf: [LbaFormatData; 64],
+    pub vs: [u8; 3712],
+}
+
+const _: () = assert!(size_of::<NamespaceData>() == 4096);
+
+/// LBA Format data structure, 4 bytes each.
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
-struct LBAFormatDataStructure {
-    metadata_size: u16,
-    lba_data_size: u8,
-    other: u8,
+pub struct LbaFormatData {
+    pub ms: u16,
+    pub lbads: u8,
+    pub rp: u8,
 }
 
-impl LBAFormatDataStructure {
-    fn relative_performance(&self) -> bool {
-        self.other & 1 > 0
+/// Decoded Identify Namespace data.
+#[derive(Debug, Clone)]
+pub struct NamespaceDataStructure {
+    /// NSZE, in logical blocks.
+    pub namespace_size: u64,
+    /// NCAP, in logical blocks.
+    pub namespace_capacity: u64,
+    /// NUSE, in logical blocks.
+    pub namespace_utilization: u64,
+    /// NSFEAT, namespace features.
+    pub nsfeat: u8,
+    /// Every LBA format the namespace supports, indexed by format number.
+    pub lba_formats: Vec<LbaFormat>,
+    /// Index into `lba_formats` the namespace is formatted with.
+    pub formatted_lba_index: usize,
+    /// Metadata is transferred at the end of each LBA rather than in a
+    /// separate buffer.
+    pub metadata_extended: bool,
+    /// MC, metadata capabilities.
+    pub metadata_capabilities: u8,
+    /// DPC, end-to-end data protection capabilities.
+    pub dpc: u8,
+    /// DPS, end-to-end data protection type settings.
+    pub dps: u8,
+    /// NMIC, multi-path I/O and namespace sharing capabilities.
+    pub nmic: u8,
+    /// RESCAP, reservation capabilities.
+    pub rescap: u8,
+    /// FPI, format progress indicator.
+    pub fpi: u8,
+    /// DLFEAT, deallocate logical block features.
+    pub dlfeat: u8,
+    /// NAWUN, 0's based, in logical blocks.
+    pub nawun: u16,
+    /// NAWUPF, 0's based, in logical blocks.
+    pub nawupf: u16,
+    /// NACWU, 0's based, in logical blocks.
+    pub nacwu: u16,
+    /// NABSN, 0's based, in logical blocks.
+    pub nabsn: u16,
+    /// NABO, in logical blocks.
+    pub nabo: u16,
+    /// NABSPF, 0's based, in logical blocks.
+    pub nabspf: u16,
+    /// NOIOB, optimal I/O boundary in logical blocks.
+    pub noiob: u16,
+    /// NVMCAP, in bytes.
+    pub nvm_capacity: u128,
+    /// NPWG, preferred write granularity, 0's based.
+    pub npwg: u16,
+    /// NPWA, preferred write alignment, 0's based.
+    pub npwa: u16,
+    /// NPDG, preferred deallocate granularity, 0's based.
+    pub npdg: u16,
+    /// NPDA, preferred deallocate alignment, 0's based.
+    pub npda: u16,
+    /// NOWS, optimal write size, 0's based.
+    pub nows: u16,
+    pub ana_group_id: u32,
+    /// NSATTR, bit 0 set if the namespace is write protected.
+    pub nsattr: u8,
+    pub nvm_set_id: u16,
+    pub endurance_group_id: u16,
+    pub nguid: [u8; 16],
+    pub eui64: [u8; 8],
+}
+
+impl NamespaceDataStructure {
+    /// The LBA format the namespace is formatted with, `None` if FLBAS
+    /// points past the reported formats.
+    pub fn lba_format(&self) -> Option<&LbaFormat> {
+        self.lba_formats.get(self.formatted_lba_index)
+    }
+
+    /// Data bytes per logical block, 0 without a valid LBA format.
+    pub fn lba_size(&self) -> usize {
+        self.lba_format().map_or(0, |f| f.data_size())
+    }
+
+    /// Metadata bytes per logical block, 0 without a valid LBA format.
+    pub fn metadata_size(&self) -> usize {
+        self.lba_format().map_or(0, |f| f.metadata_size as _)
+    }
+}
+
+/// One supported LBA format of a namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LbaFormat {
+    /// Metadata bytes per logical block.
+    pub metadata_size: u16,
+    /// LBADS, log2 of the data bytes per logical block.
+    pub lba_data_size: u8,
+    /// 0 best performance, 3 degraded.
+    pub relative_performance: u8,
+}
+
+impl LbaFormat {
+    /// Data bytes per logical block.
+    pub fn data_size(&self) -> usize {
+        1 << self.lba_data_size
     }
 }
 
+pub struct IdentifyNamespaceIdDescriptorList {
+    command_set: CommandSet,
+}
+
+impl IdentifyNamespaceIdDescriptorList {
+    pub fn new(nsid: u32) -> Self {
+        let command_set = CommandSet {
+            nsid,
+            ..Default::default()
+        };
+        Self { command_set }
+    }
+}
+
+impl Identify for IdentifyNamespaceIdDescriptorList {
+    const CNS: u32 = 0x03;
+
+    type Output = NamespaceIdentifiers;
+
+    fn parse(&self, data: &[u8]) -> Self::Output {
+        let mut ids = NamespaceIdentifiers::default();
+
+        // each descriptor is NIDT, NIDL, two reserved bytes and NIDL bytes of NID
+        let mut offset = 0;
+        while offset + 4 <= data.len() {
+            let nidt = data[offset];
+            let nidl = data[offset + 1] as usize;
+            let start = offset + 4;
+            if nidt == 0 || start + nidl > data.len() {
+                break;
+            }
+            let nid = &data[start..start + nidl];
+
+            match (nidt, nidl) {
+                (0x1, 8) => ids.eui64 = nid.try_into().ok(),
+                (0x2, 16) => ids.nguid = nid.try_into().ok(),
+                (0x3, 16) => ids.uuid = nid.try_into().ok(),
+                (0x4, 1) => ids.csi = Some(nid[0]),
+                _ => debug!("unknown namespace identifier type {nidt:#x}, length {nidl}"),
+            }
+
+            offset = start + nidl;
+        }
+
+        ids
+    }
+
+    fn command_set_mut(&mut self) -> &mut CommandSet {
+        &mut self.command_set
+    }
+}
+
+/// Globally unique identifiers of a namespace, from the Namespace
+/// Identification Descriptor list. They stay the same across resets and
+/// changes of the namespace id.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NamespaceIdentifiers {
+    pub eui64: Option<[u8; 8]>,
+    pub nguid: Option<[u8; 16]>,
+    pub uuid: Option<[u8; 16]>,
+    /// Command Set Identifier, 0 for the NVM command set.
+    pub csi: Option<u8>,
+}
+
+#[derive(Default)]
 pub struct IdentifyController {
     command_set: CommandSet,
 }
@@ -212,15 +644,80 @@ impl Identify for IdentifyController {
             (ptr as *const ControllerData).read_volatile()
         };
 
+        // NPSS is 0's based, the structure holds at most 32 descriptors
+        let power_states = raw.psd[..=(raw.npss as usize).min(31)]
+            .iter()
+            .map(PowerState::from)
+            .collect();
+
         ControllerInfo {
-            vendor_id: raw.vendor_id,
-            product_id: raw.product_id,
+            vendor_id: raw.vid,
+            subsystem_vendor_id: raw.ssvid,
+            serial_number: ascii_string(&raw.sn),
+            model_number: ascii_string(&raw.mn),
+            firmware_revision: ascii_string(&raw.fr),
+            recommended_arbitration_burst: raw.rab,
+            ieee_oui: raw.ieee,
+            cmic: raw.cmic,
+            max_data_transfer_size: raw.mdts,
+            controller_id: raw.cntlid,
+            version: raw.ver,
+            rtd3_resume_latency: raw.rtd3r,
+            rtd3_entry_latency: raw.rtd3e,
+            oaes: raw.oaes,
+            ctratt: raw.ctratt,
+            read_recovery_levels: raw.rrls,
+            controller_type: raw.cntrltype,
+            fru_guid: raw.fguid,
+            command_retry_delay_times: [raw.crdt1, raw.crdt2, raw.crdt3],
+            oacs: raw.oacs,
+            abort_command_limit: raw.acl,
+            async_event_request_limit: raw.aerl,
+            frmw: raw.frmw,
+            lpa: raw.lpa,
+            error_log_page_entries: raw.elpe,
+            avscc: raw.avscc,
+            apsta: raw.apsta,
+            warning_temperature: raw.wctemp,
+            critical_temperature: raw.cctemp,
+            max_time_firmware_activation: raw.mtfa,
+            host_memory_buffer_preferred: raw.hmpre,
+            host_memory_buffer_min: raw.hmmin,
+            total_capacity: u128::from_le_bytes(raw.tnvmcap),
+            unallocated_capacity: u128::from_le_bytes(raw.unvmcap),
+            rpmbs: raw.rpmbs,
+            device_self_test_time: raw.edstt,
+            device_self_test_options: raw.dsto,
+            firmware_update_granularity: raw.fwug,
+            keep_alive_support: raw.kas,
+            hctma: raw.hctma,
+            min_thermal_management_temperature: raw.mntmt,
+            max_thermal_management_temperature: raw.mxtmt,
+            sanicap: raw.sanicap,
+            ana_transition_time: raw.anatt,
+            ana_capabilities: raw.anacap,
+            ana_group_id_max: raw.anagrpmax,
+            ana_group_count: raw.nanagrpid,
+            persistent_event_log_size: raw.pels,
             sqes_max: raw.sqes >> 4,
             sqes_min: raw.sqes & 0b1111,
             cqes_max: raw.cqes >> 4,
             cqes_min: raw.cqes & 0b1111,
-            max_cmd: raw.max_cmd,
-            number_of_namespaces: raw.number_of_namespaces,
+            max_cmd: raw.maxcmd,
+            number_of_namespaces: raw.nn,
+            oncs: raw.oncs,
+            fuses: raw.fuses,
+            fna: raw.fna,
+            vwc: raw.vwc,
+            awun: raw.awun,
+            awupf: raw.awupf,
+            nvscc: raw.nvscc,
+            nwpc: raw.nwpc,
+            acwu: raw.acwu,
+            sgl_support: SglSupport(raw.sgls),
+            max_allowed_namespaces: raw.mnan,
+            subnqn: ascii_string(&raw.subnqn),
+            power_states,
         }
     }
 
@@ -229,28 +726,292 @@ impl Identify for IdentifyController {
     }
 }
 
+// text fields are space padded ASCII, subnqn is NUL terminated
+fn ascii_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).trim_end().into()
+}
+
+/// Identify Controller data structure (CNS 01h), as laid out by the spec.
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct ControllerData {
-    pub vendor_id: u16,
-    pub product_id: u16,
-    pub serial_number: [u8; 20],
-    pub model_number: [u8; 40],
-    pub firmware_revision: [u8; 8],
-    pub rsv: [u8; 512 - 8 - 40 - 20 - 2 - 2],
+    pub vid: u16,
+    pub ssvid: u16,
+    pub sn: [u8; 20],
+    pub mn: [u8; 40],
+    pub fr: [u8; 8],
+    pub rab: u8,
+    pub ieee: [u8; 3],
+    pub cmic: u8,
+    pub mdts: u8,
+    pub cntlid: u16,
+    pub ver: u32,
+    pub rtd3r: u32,
+    pub rtd3e: u32,
+    pub oaes: u32,
+    pub ctratt: u32,
+    pub rrls: u16,
+    pub rsv102: [u8; 9],
+    pub cntrltype: u8,
+    pub fguid: [u8; 16],
+    pub crdt1: u16,
+    pub crdt2: u16,
+    pub crdt3: u16,
+    pub rsv134: [u8; 119],
+    pub nvmsr: u8,
+    pub vwci: u8,
+    pub mec: u8,
+    pub oacs: u16,
+    pub acl: u8,
+    pub aerl: u8,
+    pub frmw: u8,
+    pub lpa: u8,
+    pub elpe: u8,
+    pub npss: u8,
+    pub avscc: u8,
+    pub apsta: u8,
+    pub wctemp: u16,
+    pub cctemp: u16,
+    pub mtfa: u16,
+    pub hmpre: u32,
+    pub hmmin: u32,
+    pub tnvmcap: [u8; 16],
+    pub unvmcap: [u8; 16],
+    pub rpmbs: u32,
+    pub edstt: u16,
+    pub dsto: u8,
+    pub fwug: u8,
+    pub kas: u16,
+    pub hctma: u16,
+    pub mntmt: u16,
+    pub mxtmt: u16,
+    pub sanicap: u32,
+    pub hmminds: u32,
+    pub hmmaxd: u16,
+    pub nsetidmax: u16,
+    pub endgidmax: u16,
+    pub anatt: u8,
+    pub anacap: u8,
+    pub anagrpmax: u32,
+    pub nanagrpid: u32,
+    pub pels: u32,
+    pub rsv356: [u8; 156],
     pub sqes: u8,
     pub cqes: u8,
-    pub max_cmd: u16,
-    pub number_of_namespaces: u32,
+    pub maxcmd: u16,
+    pub nn: u32,
+    pub oncs: u16,
+    pub fuses: u16,
+    pub fna: u8,
+    pub vwc: u8,
+    pub awun: u16,
+    pub awupf: u16,
+    pub nvscc: u8,
+    pub nwpc: u8,
+    pub acwu: u16,
+    pub rsv534: [u8; 2],
+    pub sgls: u32,
+    pub mnan: u32,
+    pub rsv544: [u8; 224],
+    pub subnqn: [u8; 256],
+    pub rsv1024: [u8; 1024],
+    pub psd: [PowerStateDescriptor; 32],
+    pub vs: [u8; 1024],
+}
+
+const _: () = assert!(size_of::<ControllerData>() == 4096);
+
+/// Power State Descriptor, 32 bytes each.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PowerStateDescriptor {
+    pub mp: u16,
+    pub rsv2: u8,
+    /// bit 0 MXPS, bit 1 NOPS
+    pub flags: u8,
+    pub enlat: u32,
+    pub exlat: u32,
+    pub rrt: u8,
+    pub rrl: u8,
+    pub rwt: u8,
+    pub rwl: u8,
+    pub idlp: u16,
+    pub ips: u8,
+    pub rsv19: u8,
+    pub actp: u16,
+    pub apw_aps: u8,
+    pub rsv23: [u8; 9],
 }
 
-#[derive(Debug)]
+/// Decoded Identify Controller data.
+#[derive(Debug, Clone)]
 pub struct ControllerInfo {
     pub vendor_id: u16,
-    pub product_id: u16,
+    pub subsystem_vendor_id: u16,
+    pub serial_number: String,
+    pub model_number: String,
+    pub firmware_revision: String,
+    pub recommended_arbitration_burst: u8,
+    pub ieee_oui: [u8; 3],
+    /// CMIC, multi-path I/O and namespace sharing capabilities.
+    pub cmic: u8,
+    /// MDTS, as a power of two in units of CAP.MPSMIN. 0 means no limit.
+    pub max_data_transfer_size: u8,
+    /// CNTLID
+    pub controller_id: u16,
+    /// VER, in the layout of the VS register.
+    pub version: u32,
+    /// RTD3R, in microseconds.
+    pub rtd3_resume_latency: u32,
+    /// RTD3E, expected time to finish a shutdown in microseconds. 0 if not
+    /// reported.
+    pub rtd3_entry_latency: u32,
+    /// OAES, optional asynchronous events supported.
+    pub oaes: u32,
+    /// CTRATT, controller attributes.
+    pub ctratt: u32,
+    /// RRLS, bit n set if read recovery level n is supported.
+    pub read_recovery_levels: u16,
+    /// CNTRLTYPE, 1 I/O, 2 discovery, 3 administrative. 0 if not reported.
+    pub controller_type: u8,
+    pub fru_guid: [u8; 16],
+    /// CRDT1 to CRDT3, in units of 100 milliseconds.
+    pub command_retry_delay_times: [u16; 3],
+    /// OACS, optional admin commands supported.
+    pub oacs: u16,
+    /// ACL, 0's based.
+    pub abort_command_limit: u8,
+    /// AERL, 0's based.
+    pub async_event_request_limit: u8,
+    /// FRMW, firmware slots and update behaviour.
+    pub frmw: u8,
+    /// LPA, log page attributes.
+    pub lpa: u8,
+    /// ELPE, Error Information log entries, 0's based.
+    pub error_log_page_entries: u8,
+    pub avscc: u8,
+    /// APSTA, autonomous power state transitions supported.
+    pub apsta: u8,
+    /// WCTEMP, in Kelvin.
+    pub warning_temperature: u16,
+    /// CCTEMP, in Kelvin.
+    pub critical_temperature: u16,
+    /// MTFA, in units of 100 milliseconds.
+    pub max_time_firmware_activation: u16,
+    /// HMPRE, in 4 KiB units.
+    pub host_memory_buffer_preferred: u32,
+    /// HMMIN, in 4 KiB units.
+    pub host_memory_buffer_min: u32,
+    /// TNVMCAP, in bytes.
+    pub total_capacity: u128,
+    /// UNVMCAP, in bytes.
+    pub unallocated_capacity: u128,
+    pub rpmbs: u32,
+    /// EDSTT, extended device self-test time in minutes.
+    pub device_self_test_time: u16,
+    pub device_self_test_options: u8,
+    /// FWUG, in 4 KiB units. 0 if not reported, 0xFF if there is no
+    /// restriction.
+    pub firmware_update_granularity: u8,
+    /// KAS, in units of 100 milliseconds.
+    pub keep_alive_support: u16,
+    /// HCTMA, host controlled thermal management attributes.
+    pub hctma: u16,
+    /// MNTMT, in Kelvin.
+    pub min_thermal_management_temperature: u16,
+    /// MXTMT, in Kelvin.
+    pub max_thermal_management_temperature: u16,
+    /// SANICAP, sanitize capabilities.
+    pub sanicap: u32,
+    /// ANATT, in seconds.
+    pub ana_transition_time: u8,
+    pub ana_capabilities: u8,
+    pub ana_group_id_max: u32,
+    pub ana_group_count: u32,
+    /// PELS, in 64 KiB units.
+    pub persistent_event_log_size: u32,
     pub sqes_max: u8,
     pub sqes_min: u8,
     pub cqes_max: u8,
     pub cqes_min: u8,
     pub max_cmd: u16,
     pub number_of_namespaces: u32,
+    /// ONCS, optional NVM commands supported.
+    pub oncs: u16,
+    /// FUSES, fused operations supported.
+    pub fuses: u16,
+    /// FNA, format NVM attributes.
+    pub fna: u8,
+    /// VWC, volatile write cache.
+    pub vwc: u8,
+    pub awun: u16,
+    pub awupf: u16,
+    pub nvscc: u8,
+    pub nwpc: u8,
+    pub acwu: u16,
+    pub sgl_support: SglSupport,
+    /// MNAN, 0 if not reported.
+    pub max_allowed_namespaces: u32,
+    /// NVM Subsystem NVMe Qualified Name.
+    pub subnqn: String,
+    /// One entry per supported power state, indexed by power state.
+    pub power_states: Vec<PowerState>,
+}
+
+/// Decoded power state descriptor.
+#[derive(Debug, Clone, Copy)]
+pub struct PowerState {
+    /// MP, maximum power in units of [`PowerState::power_scale_uw`].
+    pub max_power: u16,
+    /// Non-operational state, no I/O commands are processed in it.
+    pub non_operational: bool,
+    /// ENLAT, entry latency in microseconds.
+    pub entry_latency: u32,
+    /// EXLAT, exit latency in microseconds.
+    pub exit_latency: u32,
+    pub relative_read_throughput: u8,
+    pub relative_read_latency: u8,
+    pub relative_write_throughput: u8,
+    pub relative_write_latency: u8,
+    /// IDLP, in units of the IPS scale.
+    pub idle_power: u16,
+    pub idle_power_scale: u8,
+    /// ACTP, in units of the APS scale.
+    pub active_power: u16,
+    pub active_power_workload: u8,
+    pub active_power_scale: u8,
+    scale_small: bool,
+}
+
+impl PowerState {
+    /// Unit of [`PowerState::max_power`] in microwatts.
+    pub fn power_scale_uw(&self) -> u32 {
+        if self.scale_small {
+            100
+        } else {
+            10_000
+        }
+    }
+}
+
+impl From<&PowerStateDescriptor> for PowerState {
+    fn from(d: &PowerStateDescriptor) -> Self {
+        Self {
+            max_power: d.mp,
+            non_operational: d.flags & 0b10 != 0,
+            entry_latency: d.enlat,
+            exit_latency: d.exlat,
+            relative_read_throughput: d.rrt & 0x1f,
+            relative_read_latency: d.rrl & 0x1f,
+            relative_write_throughput: d.rwt & 0x1f,
+            relative_write_latency: d.rwl & 0x1f,
+            idle_power: d.idlp,
+            idle_power_scale: d.ips >> 6,
+            active_power: d.actp,
+            active_power_workload: d.apw_aps & 0b111,
+            active_power_scale: d.apw_aps >> 6,
+            scale_small: d.flags & 1 != 0,
+        }
+    }
 }
@@ -1,8 +1,241 @@
+use crate::log_page::ErrorLogEntry;
+
 #[derive(Debug, Clone, Copy)]
 pub enum Error {
     NoMemory,
     Layout,
+    NotSupported(&'static str),
+    InvalidParameter(&'static str),
+    /// Every command slot of the queue is in use.
+    QueueFull,
+    /// The controller did not respond in time.
+    Timeout,
+    /// CSTS.CFS is set, the controller needs a reset.
+    ControllerFatal,
+    /// The queue was deleted by [`crate::Nvme::shutdown`], or its
+    /// [`crate::Nvme`] was dropped.
+    QueueDeleted,
+    /// The controller completed the command with an error status.
+    Command(CommandError),
     Unknown(&'static str),
 }
 
 pub type Result<T = ()> = core::result::Result<T, Error>;
+
+/// Error status posted in a completion queue entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandError {
+    pub status: Status,
+    /// Submission queue the command was issued on.
+    pub sqid: u16,
+    pub cid: u16,
+    /// More information is available in the Error Information log page.
+    pub more: bool,
+    /// Retrying the same command is expected to fail again.
+    pub do_not_retry: bool,
+    /// Command specific dword 0 of the completion entry.
+    pub result: u32,
+    /// Error Information log entry of the command, if capturing is enabled
+    /// and the controller logged one.
+    pub log: Option<ErrorLogEntry>,
+}
+
+/// Status Code Type together with its Status Code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Generic(GenericStatus),
+    CommandSpecific(CommandSpecificStatus),
+    MediaDataIntegrity(MediaStatus),
+    PathRelated(PathStatus),
+    VendorSpecific(u8),
+    Reserved { sct: u8, sc: u8 },
+}
+
+impl Status {
+    pub fn new(sct: u8, sc: u8) -> Self {
+        match sct {
+            0 => Self::Generic(sc.into()),
+            1 => Self::CommandSpecific(sc.into()),
+            2 => Self::MediaDataIntegrity(sc.into()),
+            3 => Self::PathRelated(sc.into()),
+            7 => Self::VendorSpecific(sc),
+            sct => Self::Reserved { sct, sc },
+        }
+    }
+
+    /// Status Code Type (SCT).
+    pub fn code_type(&self) -> u8 {
+        match self {
+            Self::Generic(_) => 0,
+            Self::CommandSpecific(_) => 1,
+            Self::MediaDataIntegrity(_) => 2,
+            Self::PathRelated(_) => 3,
+            Self::VendorSpecific(_) => 7,
+            Self::Reserved { sct, .. } => *sct,
+        }
+    }
+
+    /// Status Code (SC).
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::Generic(s) => s.code(),
+            Self::CommandSpecific(s) => s.code(),
+            Self::MediaDataIntegrity(s) => s.code(),
+            Self::PathRelated(s) => s.code(),
+            Self::VendorSpecific(sc) => *sc,
+            Self::Reserved { sc, .. } => *sc,
+        }
+    }
+}
+
+macro_rules! status_codes {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $($variant:ident = $code:literal,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum $name {
+            $($variant,)*
+            /// A code this driver has no name for.
+            Other(u8),
+        }
+
+        impl $name {
+            pub fn code(&self) -> u8 {
+                match self {
+                    $(Self::$variant => $code,)*
+                    Self::Other(code) => *code,
+                }
+            }
+        }
+
+        impl From<u8> for $name {
+            fn from(code: u8) -> Self {
+                match code {
+                    $($code => Self::$variant,)*
+                    code => Self::Other(code),
+                }
+            }
+        }
+    };
+}
+
+status_codes! {
+    /// Generic Command Status (SCT 0h).
+    pub enum GenericStatus {
+        Success = 0x00,
+        InvalidCommandOpcode = 0x01,
+        InvalidFieldInCommand = 0x02,
+        CommandIdConflict = 0x03,
+        DataTransferError = 0x04,
+        AbortedPowerLoss = 0x05,
+        InternalError = 0x06,
+        AbortRequested = 0x07,
+        AbortedSqDeletion = 0x08,
+        AbortedFailedFusedCommand = 0x09,
+        AbortedMissingFusedCommand = 0x0A,
+        InvalidNamespaceOrFormat = 0x0B,
+        CommandSequenceError = 0x0C,
+        InvalidSglSegmentDescriptor = 0x0D,
+        InvalidNumberOfSglDescriptors = 0x0E,
+        DataSglLengthInvalid = 0x0F,
+        MetadataSglLengthInvalid = 0x10,
+        SglDescriptorTypeInvalid = 0x11,
+        InvalidUseOfControllerMemoryBuffer = 0x12,
+        PrpOffsetInvalid = 0x13,
+        AtomicWriteUnitExceeded = 0x14,
+        OperationDenied = 0x15,
+        SglOffsetInvalid = 0x16,
+        HostIdentifierInconsistentFormat = 0x18,
+        KeepAliveTimerExpired = 0x19,
+        KeepAliveTimeoutInvalid = 0x1A,
+        AbortedPreemptAndAbort = 0x1B,
+        SanitizeFailed = 0x1C,
+        SanitizeInProgress = 0x1D,
+        SglDataBlockGranularityInvalid = 0x1E,
+        CommandNotSupportedForQueueInCmb = 0x1F,
+        NamespaceIsWriteProtected = 0x20,
+        CommandInterrupted = 0x21,
+        TransientTransportError = 0x22,
+        LbaOutOfRange = 0x80,
+        CapacityExceeded = 0x81,
+        NamespaceNotReady = 0x82,
+        ReservationConflict = 0x83,
+        FormatInProgress = 0x84,
+    }
+}
+
+status_codes! {
+    /// Command Specific Status (SCT 1h).
+    pub enum CommandSpecificStatus {
+        CompletionQueueInvalid = 0x00,
+        InvalidQueueIdentifier = 0x01,
+        InvalidQueueSize = 0x02,
+        AbortCommandLimitExceeded = 0x03,
+        AsynchronousEventRequestLimitExceeded = 0x05,
+        InvalidFirmwareSlot = 0x06,
+        InvalidFirmwareImage = 0x07,
+        InvalidInterruptVector = 0x08,
+        InvalidLogPage = 0x09,
+        InvalidFormat = 0x0A,
+        FirmwareActivationRequiresConventionalReset = 0x0B,
+        InvalidQueueDeletion = 0x0C,
+        FeatureIdentifierNotSaveable = 0x0D,
+        FeatureNotChangeable = 0x0E,
+        FeatureNotNamespaceSpecific = 0x0F,
+        FirmwareActivationRequiresNvmSubsystemReset = 0x10,
+        FirmwareActivationRequiresControllerLevelReset = 0x11,
+        FirmwareActivationRequiresMaximumTimeViolation = 0x12,
+        FirmwareActivationProhibited = 0x13,
+        OverlappingRange = 0x14,
+        NamespaceInsufficientCapacity = 0x15,
+        NamespaceIdentifierUnavailable = 0x16,
+        NamespaceAlreadyAttached = 0x18,
+        NamespaceIsPrivate = 0x19,
+        NamespaceNotAttached = 0x1A,
+        ThinProvisioningNotSupported = 0x1B,
+        ControllerListInvalid = 0x1C,
+        DeviceSelfTestInProgress = 0x1D,
+        BootPartitionWriteProhibited = 0x1E,
+        InvalidControllerIdentifier = 0x1F,
+        InvalidSecondaryControllerState = 0x20,
+        InvalidNumberOfControllerResources = 0x21,
+        InvalidResourceIdentifier = 0x22,
+        SanitizeProhibitedWhilePmrEnabled = 0x23,
+        AnaGroupIdentifierInvalid = 0x24,
+        AnaAttachFailed = 0x25,
+        ConflictingAttributes = 0x80,
+        InvalidProtectionInformation = 0x81,
+        AttemptedWriteToReadOnlyRange = 0x82,
+    }
+}
+
+status_codes! {
+    /// Media and Data Integrity Errors (SCT 2h).
+    pub enum MediaStatus {
+        WriteFault = 0x80,
+        UnrecoveredReadError = 0x81,
+        EndToEndGuardCheckError = 0x82,
+        EndToEndApplicationTagCheckError = 0x83,
+        EndToEndReferenceTagCheckError = 0x84,
+        CompareFailure = 0x85,
+        AccessDenied = 0x86,
+        DeallocatedOrUnwrittenLogicalBlock = 0x87,
+    }
+}
+
+status_codes! {
+    /// Path Related Status (SCT 3h).
+    pub enum PathStatus {
+        InternalPathError = 0x00,
+        AsymmetricAccessPersistentLoss = 0x01,
+        AsymmetricAccessInaccessible = 0x02,
+        AsymmetricAccessTransition = 0x03,
+        ControllerPathingError = 0x60,
+        HostPathingError = 0x70,
+        CommandAbortedByHost = 0x71,
+    }
+}
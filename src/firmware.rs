@@ -0,0 +1,194 @@
+use dma_api::{DVec, Direction};
+
+use crate::{command::ControllerInfo, err::*, nvme::Nvme, prp::PrpList, queue::CommandSet};
+
+// FWUG and the image offset are in these units
+const FWUG_UNIT: usize = 0x1000;
+// cap on one download when MDTS sets no limit
+const MAX_DOWNLOAD_CHUNK: usize = 0x10_0000;
+
+/// What Firmware Commit does with the slot (CA field).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitAction {
+    /// Store the downloaded image in the slot without activating it.
+    Replace = 0b000,
+    /// Store the downloaded image and activate it at the next reset.
+    ReplaceAndActivate = 0b001,
+    /// Activate the image already in the slot at the next reset.
+    Activate = 0b010,
+    /// Store the downloaded image and activate it right away, without a
+    /// reset.
+    ReplaceAndActivateImmediately = 0b011,
+}
+
+/// Reset needed before a committed image runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetRequired {
+    /// The image is running, or nothing was activated.
+    None,
+    /// A conventional reset: a PCIe hot, warm or cold reset, or a power
+    /// cycle. [`Nvme::reset_controller`] is not enough.
+    Conventional,
+    /// An NVM subsystem reset, see [`Nvme::subsystem_reset`].
+    Subsystem,
+    /// A controller level reset, e.g. [`Nvme::reset_controller`].
+    Controller,
+}
+
+/// Firmware Slot Information log (LID 03h).
+#[derive(Debug, Clone)]
+pub struct FirmwareSlotLog {
+    /// Slot the running firmware was loaded from.
+    pub active_slot: u8,
+    /// Slot activated at the next reset, 0 if not set.
+    pub next_slot: u8,
+    /// Revision in slots 1 to 7, `None` for empty or missing slots.
+    pub revisions: [Option<[u8; 8]>; 7],
+}
+
+impl FirmwareSlotLog {
+    pub const SIZE: usize = 512;
+
+    pub fn parse(data: &[u8]) -> Self {
+        let mut revisions = [None; 7];
+        for (i, rev) in revisions.iter_mut().enumerate() {
+            let raw: [u8; 8] = data[8 + i * 8..16 + i * 8].try_into().unwrap();
+            if raw != [0; 8] {
+                *rev = Some(raw);
+            }
+        }
+
+        Self {
+            active_slot: data[0] & 0b111,
+            next_slot: data[0] >> 4 & 0b111,
+            revisions,
+        }
+    }
+}
+
+/// Downloads a firmware image and commits it to a slot.
+///
+/// The image is sent in pieces sized by FWUG and MDTS. Nothing changes on
+/// the controller until [`FirmwareUpdater::commit`].
+pub struct FirmwareUpdater<'a> {
+    nvme: &'a mut Nvme,
+    chunk: usize,
+    // FWUG in bytes, every piece but the last must be a multiple of it
+    granularity: usize,
+    slots: u8,
+    slot1_read_only: bool,
+}
+
+impl<'a> FirmwareUpdater<'a> {
+    pub(crate) fn new(nvme: &'a mut Nvme, controller: &ControllerInfo) -> Result<Self> {
+        let slots = controller.frmw >> 1 & 0b111;
+        if slots == 0 {
+            return Err(Error::NotSupported("firmware update"));
+        }
+
+        let mut chunk = nvme.max_transfer_size().min(MAX_DOWNLOAD_CHUNK);
+        // 0 means no granularity reported, 0xFF no restriction, dwords is the
+        // least the command takes
+        let granularity = match controller.firmware_update_granularity {
+            0 | 0xFF => 4,
+            g => g as usize * FWUG_UNIT,
+        };
+        if granularity > chunk {
+            return Err(Error::NotSupported(
+                "firmware update granularity exceeds max data transfer size",
+            ));
+        }
+        chunk -= chunk % granularity;
+
+        Ok(Self {
+            nvme,
+            chunk,
+            granularity,
+            slots,
+            slot1_read_only: controller.frmw & 1 != 0,
+        })
+    }
+
+    /// Send the whole image with Firmware Image Download.
+    ///
+    /// The image size must be a multiple of the update granularity (FWUG),
+    /// or of a dword if the controller reports none.
+    pub fn download(&mut self, image: &[u8]) -> Result {
+        // checked up front, a rejected last piece would leave a partial image
+        if image.is_empty() || !image.len().is_multiple_of(self.granularity) {
+            return Err(Error::InvalidParameter(
+                "firmware image size must be a multiple of the update granularity",
+            ));
+        }
+
+        let page_size = self.nvme.page_size();
+
+        for (i, part) in image.chunks(self.chunk).enumerate() {
+            let mut buff = DVec::zeros(u64::MAX, part.len(), page_size, Direction::ToDevice)
+                .map_err(|_| Error::NoMemory)?;
+            buff.copy_from_slice(part);
+            let prp = PrpList::new(buff.bus_addr(), part.len(), page_size)?;
+            let offset = i * self.chunk;
+            let cmd = CommandSet::firmware_image_download(
+                (part.len() / 4) as u32,
+                (offset / 4) as u32,
+                &prp,
+            );
+            self.nvme.admin_command_sync(cmd)?;
+        }
+
+        Ok(())
+    }
+
+    /// Commit to `slot`, 1 to 7, or 0 to let the controller pick one.
+    pub fn commit(&mut self, slot: u8, action: CommitAction) -> Result<ResetRequired> {
+        if slot > self.slots {
+            return Err(Error::InvalidParameter("no such firmware slot"));
+        }
+        if slot == 1 && self.slot1_read_only && action != CommitAction::Activate {
+            return Err(Error::InvalidParameter("firmware slot 1 is read only"));
+        }
+
+        let cmd = CommandSet::firmware_commit(slot, action as u8);
+        match self.nvme.admin_command_sync(cmd) {
+            // activated at the next Controller Level Reset, unless the
+            // status asks for more
+            Ok(_) => Ok(match action {
+                CommitAction::Replace | CommitAction::ReplaceAndActivateImmediately => {
+                    ResetRequired::None
+                }
+                _ => ResetRequired::Controller,
+            }),
+            // the image was committed, but only runs after a reset
+            Err(e) => activation_reset(&e).ok_or(e),
+        }
+    }
+
+    /// Read the Firmware Slot Information log.
+    pub fn slot_info(&mut self) -> Result<FirmwareSlotLog> {
+        self.nvme.firmware_slot_log()
+    }
+}
+
+fn activation_reset(e: &Error) -> Option<ResetRequired> {
+    let Error::Command(CommandError {
+        status: Status::CommandSpecific(s),
+        ..
+    }) = e
+    else {
+        return None;
+    };
+
+    match s {
+        CommandSpecificStatus::FirmwareActivationRequiresConventionalReset => {
+            Some(ResetRequired::Conventional)
+        }
+        CommandSpecificStatus::FirmwareActivationRequiresNvmSubsystemReset => {
+            Some(ResetRequired::Subsystem)
+        }
+        CommandSpecificStatus::FirmwareActivationRequiresControllerLevelReset => {
+            Some(ResetRequired::Controller)
+        }
+        _ => None,
+    }
+}
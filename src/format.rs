@@ -0,0 +1,74 @@
+use core::time::Duration;
+
+// a secure erase of a large drive can take a long time
+const DEFAULT_FORMAT_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Secure Erase Settings (SES) of Format NVM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SecureErase {
+    #[default]
+    None = 0b000,
+    /// Overwrite or otherwise erase all user data.
+    UserData = 0b001,
+    /// Discard the encryption key of the user data, needs FNA bit 2.
+    Cryptographic = 0b010,
+}
+
+/// End-to-end Protection Information (PI) type of the new format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProtectionInfo {
+    #[default]
+    Disabled = 0b000,
+    Type1 = 0b001,
+    Type2 = 0b010,
+    Type3 = 0b011,
+}
+
+/// Settings for [`crate::Nvme::format_nvm`].
+#[derive(Debug, Clone, Copy)]
+pub struct FormatNvm {
+    /// Index into [`crate::NamespaceDataStructure::lba_formats`].
+    pub lba_format: u8,
+    /// Metadata is sent inline with the data (MSET), instead of in a separate
+    /// buffer.
+    pub metadata_extended: bool,
+    pub protection: ProtectionInfo,
+    /// Protection information in the first bytes of the metadata (PIL),
+    /// instead of the last.
+    pub protection_first: bool,
+    pub secure_erase: SecureErase,
+    /// How long to wait for the format to finish.
+    pub timeout: Duration,
+}
+
+impl FormatNvm {
+    pub fn new(lba_format: u8) -> Self {
+        Self {
+            lba_format,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn cdw10(&self) -> u32 {
+        let lbaf = self.lba_format as u32;
+        (lbaf & 0xF)
+            | (self.metadata_extended as u32) << 4
+            | (self.protection as u32) << 5
+            | (self.protection_first as u32) << 8
+            | (self.secure_erase as u32) << 9
+            | (lbaf >> 4 & 0b11) << 12
+    }
+}
+
+impl Default for FormatNvm {
+    fn default() -> Self {
+        Self {
+            lba_format: 0,
+            metadata_extended: false,
+            protection: ProtectionInfo::Disabled,
+            protection_first: false,
+            secure_erase: SecureErase::None,
+            timeout: DEFAULT_FORMAT_TIMEOUT,
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use core::{
+    future::Future,
+    hint::spin_loop,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{err::*, io_queue::IoQueue, queue::Request, time::Deadline};
+
+/// Resolves to the result dword of a command once it completes.
+///
+/// Once the timeout of the queue passes the command is aborted and the
+/// future resolves to [`Error::Timeout`], unless it turns out to have
+/// completed meanwhile.
+/// Dropping the future early waits for the command to finish, as the
+/// controller may still be using buffers borrowed by the caller. For the
+/// same reason it must never be leaked, see [`IoQueue::read`].
+pub struct CommandFuture<'a> {
+    queue: &'a IoQueue,
+    request: Option<Request>,
+    deadline: Deadline,
+}
+
+impl<'a> CommandFuture<'a> {
+    /// `request` must have been submitted with `NvmeQueue::submit_waited`.
+    pub fn new(queue: &'a IoQueue, request: Request) -> Self {
+        Self {
+            queue,
+            request: Some(request),
+            deadline: Deadline::after(queue.shared().with(|q| q.timeout)),
+        }
+    }
+}
+
+impl Future for CommandFuture<'_> {
+    type Output = Result<u32>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let request = self.request.expect("polled after completion");
+
+        let expired = self.deadline.expired();
+        let (done, polled) = self.queue.shared().with(|q| {
+            let done = q.take_completion(request);
+            if done.is_none() && !expired {
+                q.register_waker(request, cx.waker());
+            }
+            (done, q.interrupt_vector.is_none())
+        });
+
+        if let Some(c) = done {
+            self.request = None;
+            return Poll::Ready(c.result);
+        }
+
+        if let Err(e) = self.queue.check_fatal(request) {
+            self.request = None;
+            return Poll::Ready(Err(e));
+        }
+
+        if expired {
+            self.request = None;
+            return Poll::Ready(self.queue.recover_timeout(request));
+        }
+
+        if polled {
+            // no interrupt will drain the completion queue, ask to be polled again
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for CommandFuture<'_> {
+    fn drop(&mut self) {
+        if let Some(request) = self.request {
+            while self.queue.take_completion(request).is_none() {
+                // both give the command back or disable the controller, so
+                // it no longer touches the buffers of the command
+                if self.queue.check_fatal(request).is_err() {
+                    break;
+                }
+                if self.deadline.expired() {
+                    let _ = self.queue.recover_timeout(request);
+                    break;
+                }
+                spin_loop();
+            }
+        }
+    }
+}
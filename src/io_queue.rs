@@ -0,0 +1,647 @@
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use alloc::{sync::Arc, vec::Vec};
+use dma_api::{DSlice, DSliceMut, DVec, Direction};
+use log::{debug, warn};
+use spin::{Mutex, MutexGuard};
+
+use crate::{
+    err::*,
+    future::CommandFuture,
+    log_page::{parse_error_log, ErrorLogEntry, LogPageId, NSID_ALL},
+    nvme::Namespace,
+    prp::PrpList,
+    queue::{CommandSet, Completion, NvmeQueue, Payload, Request},
+    sgl::{SglDescriptor, SglList, SglSupport},
+    time::Deadline,
+};
+
+/// Handle to one I/O submission and completion queue pair.
+///
+/// Each handle locks only its own queue, so handles for different queues
+/// can be moved to different CPUs and used without contending.
+pub struct IoQueue {
+    qid: u16,
+    interrupt_vector: Option<u16>,
+    queue: Arc<SharedQueue>,
+    // used to abort commands that time out
+    admin: Arc<SharedQueue>,
+    page_size: usize,
+    max_transfer_size: usize,
+    sgl_support: SglSupport,
+}
+
+impl IoQueue {
+    pub(crate) fn new(
+        queue: NvmeQueue,
+        admin: Arc<SharedQueue>,
+        page_size: usize,
+        max_transfer_size: usize,
+        sgl_support: SglSupport,
+        on_reset_required: Option<fn()>,
+    ) -> Self {
+        Self {
+            qid: queue.qid as _,
+            interrupt_vector: queue.interrupt_vector,
+            queue: Arc::new(SharedQueue::new(queue, on_reset_required)),
+            admin,
+            page_size,
+            max_transfer_size,
+            sgl_support,
+        }
+    }
+
+    /// A new handle to the same queue pair.
+    pub(crate) fn handle(&self) -> Self {
+        Self {
+            queue: self.queue.clone(),
+            admin: self.admin.clone(),
+            ..*self
+        }
+    }
+
+    pub(crate) fn shared(&self) -> &SharedQueue {
+        &self.queue
+    }
+
+    /// Queue identifier used by the controller.
+    pub fn qid(&self) -> u16 {
+        self.qid
+    }
+
+    pub fn block_write_sync(&self, ns: &Namespace, block_start: u64, buff: &[u8]) -> Result<()> {
+        assert!(
+            buff.len().is_multiple_of(ns.lba_size),
+            "buffer size must be multiple of lba size"
+        );
+
+        let buff = DSlice::from(buff, Direction::Bidirectional);
+
+        let chunk = self.max_transfer_blocks(ns) * ns.lba_size;
+        let mut offset = 0;
+
+        while offset < buff.len() {
+            let len = chunk.min(buff.len() - offset);
+            let prp = PrpList::new(buff.bus_addr() + offset as u64, len, self.page_size)?;
+            let lba = block_start + (offset / ns.lba_size) as u64;
+
+            let cmd = CommandSet::nvm_cmd_write(ns.id, &prp, lba, (len / ns.lba_size) as _);
+
+            self.command_sync(cmd)?;
+
+            offset += len;
+        }
+
+        Ok(())
+    }
+
+    pub fn block_read_sync(&self, ns: &Namespace, block_start: u64, buff: &mut [u8]) -> Result<()> {
+        assert!(
+            buff.len().is_multiple_of(ns.lba_size),
+            "buffer size must be multiple of lba size"
+        );
+
+        let buff = DSliceMut::from(buff, Direction::FromDevice);
+
+        let chunk = self.max_transfer_blocks(ns) * ns.lba_size;
+        let mut offset = 0;
+
+        while offset < buff.len() {
+            let len = chunk.min(buff.len() - offset);
+            let prp = PrpList::new(buff.bus_addr() + offset as u64, len, self.page_size)?;
+            let lba = block_start + (offset / ns.lba_size) as u64;
+
+            let cmd = CommandSet::nvm_cmd_read(ns.id, &prp, lba, (len / ns.lba_size) as _);
+
+            self.command_sync(cmd)?;
+
+            offset += len;
+        }
+
+        buff.preper_read_all();
+        Ok(())
+    }
+
+    /// Write several buffers to consecutive blocks with a single command,
+    /// using an SGL so the buffers need not be contiguous.
+    pub fn block_write_vectored_sync(
+        &self,
+        ns: &Namespace,
+        block_start: u64,
+        buffs: &[&[u8]],
+    ) -> Result<()> {
+        let buffs: Vec<_> = buffs
+            .iter()
+            .map(|b| DSlice::from(b, Direction::ToDevice))
+            .collect();
+
+        let descriptors: Vec<_> = buffs
+            .iter()
+            .map(|b| SglDescriptor::data_block(b.bus_addr(), b.len() as _))
+            .collect();
+
+        let blk_num = self.check_sgl_transfer(ns, &descriptors)?;
+        let sgl = SglList::new(&descriptors, self.page_size)?;
+
+        let cmd = CommandSet::nvm_cmd_write(ns.id, &sgl, block_start, blk_num);
+
+        self.command_sync(cmd)?;
+        Ok(())
+    }
+
+    /// Read consecutive blocks into several buffers with a single command.
+    /// [`ReadSegment::Skip`] parts are discarded by the controller through
+    /// bit bucket descriptors.
+    pub fn block_read_vectored_sync(
+        &self,
+        ns: &Namespace,
+        block_start: u64,
+        segments: &mut [ReadSegment<'_>],
+    ) -> Result<()> {
+        let mut buffs = Vec::new();
+        let mut descriptors = Vec::with_capacity(segments.len());
+
+        for seg in segments.iter_mut() {
+            match seg {
+                ReadSegment::Buffer(b) => {
+                    let b = DSliceMut::from(b, Direction::FromDevice);
+                    descriptors.push(SglDescriptor::data_block(b.bus_addr(), b.len() as _));
+                    buffs.push(b);
+                }
+                ReadSegment::Skip(len) => {
+                    if !self.sgl_support.bit_bucket() {
+                        return Err(Error::NotSupported("SGL bit bucket"));
+                    }
+                    descriptors.push(SglDescriptor::bit_bucket(*len as _));
+                }
+            }
+        }
+
+        let blk_num = self.check_sgl_transfer(ns, &descriptors)?;
+        let sgl = SglList::new(&descriptors, self.page_size)?;
+
+        let cmd = CommandSet::nvm_cmd_read(ns.id, &sgl, block_start, blk_num);
+
+        self.command_sync(cmd)?;
+
+        for b in &buffs {
+            b.preper_read_all();
+        }
+        Ok(())
+    }
+
+    /// Read blocks into `buff`, resolving once the data has landed.
+    ///
+    /// # Safety
+    ///
+    /// The returned future must be polled to completion or dropped, never
+    /// leaked with [`core::mem::forget`], as the controller may still write
+    /// to `buff` until the future is done with it.
+    pub async unsafe fn read(
+        &self,
+        ns: &Namespace,
+        block_start: u64,
+        buff: &mut [u8],
+    ) -> Result<()> {
+        assert!(
+            buff.len().is_multiple_of(ns.lba_size),
+            "buffer size must be multiple of lba size"
+        );
+
+        let chunk = self.max_transfer_blocks(ns) * ns.lba_size;
+
+        for (i, part) in buff.chunks_mut(chunk).enumerate() {
+            let lba = block_start + (i * chunk / ns.lba_size) as u64;
+            let blk_num = (part.len() / ns.lba_size) as u32;
+
+            // the future waits for the command even when dropped, and the
+            // caller promised not to leak it, so the borrow of `part` outlives
+            // every DMA access to it
+            let part = unsafe { &mut *(part as *mut [u8]) };
+            let part = DSliceMut::from(part, Direction::FromDevice);
+            let prp = PrpList::new(part.bus_addr(), part.len(), self.page_size)?;
+
+            let cmd = CommandSet::nvm_cmd_read(ns.id, &prp, lba, blk_num);
+            let payload = Payload {
+                prp: Some(prp),
+                read: Some(part),
+                ..Default::default()
+            };
+
+            self.command_async(cmd, payload).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Write `buff` to consecutive blocks, resolving once the controller
+    /// has accepted the data.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`IoQueue::read`], the controller may still read `buff` until
+    /// the future is done with it.
+    pub async unsafe fn write(&self, ns: &Namespace, block_start: u64, buff: &[u8]) -> Result<()> {
+        assert!(
+            buff.len().is_multiple_of(ns.lba_size),
+            "buffer size must be multiple of lba size"
+        );
+
+        let chunk = self.max_transfer_blocks(ns) * ns.lba_size;
+
+        for (i, part) in buff.chunks(chunk).enumerate() {
+            let lba = block_start + (i * chunk / ns.lba_size) as u64;
+            let blk_num = (part.len() / ns.lba_size) as u32;
+
+            // see `read`
+            let part = unsafe { &*(part as *const [u8]) };
+            let part = DSlice::from(part, Direction::ToDevice);
+            let prp = PrpList::new(part.bus_addr(), part.len(), self.page_size)?;
+
+            let cmd = CommandSet::nvm_cmd_write(ns.id, &prp, lba, blk_num);
+            let payload = Payload {
+                prp: Some(prp),
+                write: Some(part),
+                ..Default::default()
+            };
+
+            self.command_async(cmd, payload).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Commit the volatile write cache of the namespace to media.
+    pub async fn flush(&self, ns: &Namespace) -> Result<()> {
+        let cmd = CommandSet::nvm_cmd_flush(ns.id);
+        self.command_async(cmd, Payload::default()).await?;
+        Ok(())
+    }
+
+    async fn command_async(&self, cmd: CommandSet, payload: Payload) -> Result<u32> {
+        let request = self.queue.with(|q| q.submit_waited(cmd, payload))?;
+        CommandFuture::new(self, request).await
+    }
+
+    fn command_sync(&self, cmd: CommandSet) -> Result<u32> {
+        let request = self
+            .queue
+            .with(|q| q.submit_waited(cmd, Payload::default()))?;
+        let deadline = Deadline::after(self.queue.with(|q| q.timeout));
+
+        loop {
+            if let Some(c) = self.queue.with(|q| q.take_completion(request)) {
+                return c.result.map_err(|e| self.admin.attach_error_log(e));
+            }
+            self.check_fatal(request)?;
+            if deadline.expired() {
+                return self
+                    .recover_timeout(request)
+                    .map_err(|e| self.admin.attach_error_log(e));
+            }
+            spin_loop();
+        }
+    }
+
+    /// Give up on `request` if the controller has failed.
+    pub(crate) fn check_fatal(&self, request: Request) -> Result {
+        let r = self.queue.with(|q| {
+            let r = q.check_fatal();
+            if r.is_err() {
+                q.abandon(request);
+                q.disable_controller();
+            }
+            r
+        });
+        if let Err(Error::ControllerFatal) = r {
+            self.queue.set_reset_required();
+        }
+        r
+    }
+
+    pub(crate) fn take_completion(&self, request: Request) -> Option<Completion> {
+        self.queue.with(|q| q.take_completion(request))
+    }
+
+    /// Abort a command that ran past its deadline and reclaim its slot.
+    ///
+    /// A command that completed before the abort got to it resolves with its
+    /// own result. If the controller does not give the command back within
+    /// the admin timeout, it is abandoned, the controller is disabled so it
+    /// no longer touches the buffers of the command, and flagged for a reset,
+    /// see [`crate::Config::on_reset_required`].
+    pub(crate) fn recover_timeout(&self, request: Request) -> Result<u32> {
+        warn!(
+            "queue {}: command {} timed out, aborting",
+            self.qid,
+            request.cid()
+        );
+
+        let cmd = CommandSet::abort(request.qid(), request.cid());
+        // bit 0 of dword 0 is cleared if the command was aborted
+        let aborted = matches!(self.admin.command_sync(cmd).map(|dw0| dw0 & 1), Ok(0));
+
+        // an aborted command still completes, with Abort Requested status,
+        // one that was not aborted has usually completed already
+        let deadline = Deadline::after(self.admin.with(|q| q.timeout));
+        while !deadline.expired() {
+            if let Some(c) = self.take_completion(request) {
+                return if aborted {
+                    Err(Error::Timeout)
+                } else {
+                    c.result
+                };
+            }
+            spin_loop();
+        }
+
+        warn!(
+            "queue {}: abort of command {} failed, controller reset required",
+            self.qid,
+            request.cid()
+        );
+        self.queue.with(|q| {
+            q.abandon(request);
+            q.disable_controller();
+        });
+        self.queue.set_reset_required();
+        Err(Error::Timeout)
+    }
+
+    /// Submit a read without waiting for it. The returned [`Request`] shows
+    /// up in [`IoQueue::poll_completions`] once the data has landed in `buff`.
+    ///
+    /// # Safety
+    ///
+    /// `buff` must stay valid and must not be accessed until the completion
+    /// of the returned request has been polled.
+    pub unsafe fn submit_read(
+        &self,
+        ns: &Namespace,
+        block_start: u64,
+        buff: &mut [u8],
+    ) -> Result<Request> {
+        let blk_num = self.check_transfer(ns, buff.len())?;
+
+        let buff = unsafe { &mut *(buff as *mut [u8]) };
+        let buff = DSliceMut::from(buff, Direction::FromDevice);
+        let prp = PrpList::new(buff.bus_addr(), buff.len(), self.page_size)?;
+
+        let cmd = CommandSet::nvm_cmd_read(ns.id, &prp, block_start, blk_num);
+
+        let payload = Payload {
+            prp: Some(prp),
+            read: Some(buff),
+            ..Default::default()
+        };
+
+        self.queue.with(|q| q.submit(cmd, payload))
+    }
+
+    /// Submit a write without waiting for it.
+    ///
+    /// # Safety
+    ///
+    /// `buff` must stay valid and must not be modified until the completion
+    /// of the returned request has been polled.
+    pub unsafe fn submit_write(
+        &self,
+        ns: &Namespace,
+        block_start: u64,
+        buff: &[u8],
+    ) -> Result<Request> {
+        let blk_num = self.check_transfer(ns, buff.len())?;
+
+        let buff = unsafe { &*(buff as *const [u8]) };
+        let buff = DSlice::from(buff, Direction::ToDevice);
+        let prp = PrpList::new(buff.bus_addr(), buff.len(), self.page_size)?;
+
+        let cmd = CommandSet::nvm_cmd_write(ns.id, &prp, block_start, blk_num);
+
+        let payload = Payload {
+            prp: Some(prp),
+            write: Some(buff),
+            ..Default::default()
+        };
+
+        self.queue.with(|q| q.submit(cmd, payload))
+    }
+
+    /// Submit a flush of the volatile write cache without waiting for it.
+    pub fn submit_flush(&self, ns: &Namespace) -> Result<Request> {
+        let cmd = CommandSet::nvm_cmd_flush(ns.id);
+        self.queue.with(|q| q.submit(cmd, Payload::default()))
+    }
+
+    /// Drain the completion queue after its interrupt fired, waking the
+    /// tasks whose commands finished.
+    pub fn handle_irq(&self) {
+        self.queue.handle_irq();
+    }
+
+    /// Interrupt vector the completion queue is bound to, `None` if polled.
+    pub fn interrupt_vector(&self) -> Option<u16> {
+        self.interrupt_vector
+    }
+
+    /// Collect every I/O command finished since the last call.
+    pub fn poll_completions(&self) -> Vec<Completion> {
+        self.queue.with(|q| q.poll_completions())
+    }
+
+    // validates an SGL transfer and returns its length in blocks
+    fn check_sgl_transfer(&self, ns: &Namespace, descriptors: &[SglDescriptor]) -> Result<u32> {
+        if !self.sgl_support.is_supported() {
+            return Err(Error::NotSupported("SGL"));
+        }
+
+        if self.sgl_support.dword_aligned()
+            && descriptors
+                .iter()
+                .any(|d| d.address % 4 != 0 || d.length % 4 != 0)
+        {
+            return Err(Error::InvalidParameter("SGL data must be dword aligned"));
+        }
+
+        let len = descriptors.iter().map(|d| d.length as usize).sum();
+        self.check_transfer(ns, len)
+    }
+
+    // validates the length of a single command transfer and returns it in blocks
+    fn check_transfer(&self, ns: &Namespace, len: usize) -> Result<u32> {
+        assert!(
+            len > 0 && len.is_multiple_of(ns.lba_size),
+            "buffer size must be multiple of lba size"
+        );
+
+        let blk_num = len / ns.lba_size;
+        if blk_num > self.max_transfer_blocks(ns) {
+            return Err(Error::InvalidParameter(
+                "transfer exceeds max data transfer size",
+            ));
+        }
+
+        Ok(blk_num as _)
+    }
+
+    // blocks a single command may move, bounded by MDTS and the 16 bit NLB field
+    fn max_transfer_blocks(&self, ns: &Namespace) -> usize {
+        (self.max_transfer_size / ns.lba_size).clamp(1, 1 << 16)
+    }
+}
+
+/// One part of a vectored read.
+pub enum ReadSegment<'a> {
+    /// Data for this part lands in the buffer.
+    Buffer(&'a mut [u8]),
+    /// This many bytes are read and thrown away.
+    Skip(usize),
+}
+
+// 64 entries of the Error Information log
+const ERROR_LOG_CAPTURE_SIZE: usize = 0x1000;
+
+/// A queue shared by its handles and the interrupt handler.
+pub(crate) struct SharedQueue {
+    queue: Mutex<NvmeQueue>,
+    // set by an interrupt that found the queue locked
+    irq_pending: AtomicBool,
+    // set when a command could not be aborted after timing out
+    reset_required: AtomicBool,
+    // admin queue only, see `SharedQueue::attach_error_log`
+    capture_error_log: AtomicBool,
+    on_reset_required: Option<fn()>,
+}
+
+impl SharedQueue {
+    pub fn new(queue: NvmeQueue, on_reset_required: Option<fn()>) -> Self {
+        Self {
+            queue: Mutex::new(queue),
+            irq_pending: AtomicBool::new(false),
+            reset_required: AtomicBool::new(false),
+            capture_error_log: AtomicBool::new(false),
+            on_reset_required,
+        }
+    }
+
+    /// Flag the controller for a reset, telling the owner of the
+    /// [`crate::Nvme`] the first time.
+    pub fn set_reset_required(&self) {
+        if !self.reset_required.swap(true, Ordering::AcqRel) {
+            if let Some(f) = self.on_reset_required {
+                f();
+            }
+        }
+    }
+
+    /// Like [`NvmeQueue::command_sync`], flagging the controller for a reset
+    /// if the command times out.
+    pub fn command_sync(&self, cmd: CommandSet) -> Result<u32> {
+        let r = self.with(|q| q.command_sync(cmd));
+        if let Err(Error::Timeout | Error::ControllerFatal) = r {
+            self.set_reset_required();
+        }
+        r.map_err(|e| self.attach_error_log(e))
+    }
+
+    /// [`SharedQueue::command_sync`] with a longer timeout than the queue
+    /// default, for slow admin commands.
+    pub fn command_sync_timeout(&self, cmd: CommandSet, timeout: Duration) -> Result<u32> {
+        let saved = self.with(|q| core::mem::replace(&mut q.timeout, timeout));
+        let r = self.command_sync(cmd);
+        self.with(|q| q.timeout = saved);
+        r
+    }
+
+    pub fn set_capture_error_log(&self, capture: bool) {
+        self.capture_error_log.store(capture, Ordering::Release);
+    }
+
+    /// If enabled, look up the Error Information log entry of a failed
+    /// command and attach it to the error. Must be called on the admin queue.
+    pub fn attach_error_log(&self, e: Error) -> Error {
+        let Error::Command(mut ce) = e else {
+            return e;
+        };
+        if !self.capture_error_log.load(Ordering::Acquire) {
+            return e;
+        }
+
+        match self.read_error_log() {
+            Ok(entries) => {
+                ce.log = entries
+                    .into_iter()
+                    .find(|l| l.sqid == ce.sqid && l.cid == ce.cid)
+            }
+            Err(e) => debug!("failed to capture error log: {e:?}"),
+        }
+        Error::Command(ce)
+    }
+
+    // the newest entries of the Error Information log, one page worth
+    fn read_error_log(&self) -> Result<Vec<ErrorLogEntry>> {
+        let len = ERROR_LOG_CAPTURE_SIZE;
+        let buff = DVec::<u8>::zeros(u64::MAX, len, len, Direction::FromDevice)
+            .map_err(|_| Error::NoMemory)?;
+        // aligned to its own size, so it never crosses a memory page
+        let prp = PrpList::new(buff.bus_addr(), len, len)?;
+        let cmd = CommandSet::get_log_page(
+            LogPageId::ERROR_INFORMATION,
+            NSID_ALL,
+            0,
+            (len / 4) as u32,
+            &prp,
+        );
+        // not through `command_sync`, a failure here must not recurse
+        self.with(|q| q.command_sync(cmd))?;
+        Ok(parse_error_log(buff.as_ref()))
+    }
+
+    pub fn reset_required(&self) -> bool {
+        self.reset_required.load(Ordering::Acquire)
+    }
+
+    pub fn clear_reset_required(&self) {
+        self.reset_required.store(false, Ordering::Release);
+    }
+
+    pub fn with<R>(&self, f: impl FnOnce(&mut NvmeQueue) -> R) -> R {
+        let mut queue = self.queue.lock();
+        let r = f(&mut queue);
+        self.unlock(queue);
+        r
+    }
+
+    // release the lock, first draining what an interrupt left behind
+    fn unlock<'a>(&'a self, mut queue: MutexGuard<'a, NvmeQueue>) {
+        loop {
+            if self.irq_pending.swap(false, Ordering::AcqRel) {
+                queue.drain();
+            }
+            drop(queue);
+
+            // an interrupt may have come in after the check above
+            if !self.irq_pending.load(Ordering::Acquire) {
+                return;
+            }
+            match self.queue.try_lock() {
+                Some(q) => queue = q,
+                // the holder drains on its way out
+                None => return,
+            }
+        }
+    }
+
+    /// Never spins on the lock, so it is safe to call from an interrupt
+    /// that preempted a holder on the same CPU.
+    pub fn handle_irq(&self) {
+        self.irq_pending.store(true, Ordering::Release);
+        if let Some(queue) = self.queue.try_lock() {
+            self.unlock(queue);
+        }
+    }
+}
@@ -4,13 +4,40 @@ extern crate alloc;
 
 mod command;
 pub mod err;
+mod firmware;
+mod format;
+mod future;
+mod io_queue;
+mod log_page;
+mod namespace;
 mod nvme;
+mod prp;
 mod queue;
 mod registers;
+mod sanitize;
+mod sgl;
+mod time;
 
 use core::{alloc::Layout, ptr::NonNull};
 
-pub use nvme::{Config, Namespace, Nvme};
+pub use command::{
+    ControllerInfo, Identify, IdentifyActiveNamespaceList, IdentifyAllocatedNamespace,
+    IdentifyAllocatedNamespaceList, IdentifyController, IdentifyControllerList,
+    IdentifyIoCommandSet, IdentifyIoCommandSetController, IdentifyNamespaceControllerList,
+    IdentifyNamespaceDataStructure, IdentifyNamespaceIdDescriptorList, LbaFormat,
+    NamespaceDataStructure, NamespaceIdentifiers, PowerState,
+};
+pub use firmware::{CommitAction, FirmwareSlotLog, FirmwareUpdater, ResetRequired};
+pub use format::{FormatNvm, ProtectionInfo, SecureErase};
+pub use io_queue::{IoQueue, ReadSegment};
+pub use log_page::{ErrorLogEntry, LogPageId, SanitizeState, SanitizeStatus, SmartLog, NSID_ALL};
+pub use namespace::CreateNamespace;
+pub use nvme::{Config, InterruptMode, Namespace, Nvme, ShutdownKind};
+pub use queue::{CommandSet, Completion, Request};
+pub use registers::{ControllerStatus, ShutdownStatus};
+pub use sanitize::{Sanitize, SanitizeAction};
+pub use sgl::SglSupport;
+pub use time::{set_time_source, TimeSource};
 
 #[derive(Clone, Copy)]
 pub struct DMAMem {
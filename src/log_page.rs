@@ -0,0 +1,256 @@
+use alloc::vec::Vec;
+
+use crate::err::Status;
+
+/// NSID addressing every namespace, or none for logs that are not namespace
+/// specific.
+pub const NSID_ALL: u32 = 0xFFFF_FFFF;
+
+/// Log page identifiers (LID) for [`crate::Nvme::get_log_page`].
+pub struct LogPageId;
+
+impl LogPageId {
+    pub const ERROR_INFORMATION: u8 = 0x01;
+    pub const SMART_HEALTH: u8 = 0x02;
+    pub const FIRMWARE_SLOT: u8 = 0x03;
+    pub const CHANGED_NAMESPACE_LIST: u8 = 0x04;
+    pub const COMMANDS_SUPPORTED_AND_EFFECTS: u8 = 0x05;
+    pub const DEVICE_SELF_TEST: u8 = 0x06;
+    pub const TELEMETRY_HOST_INITIATED: u8 = 0x07;
+    pub const TELEMETRY_CONTROLLER_INITIATED: u8 = 0x08;
+    pub const ENDURANCE_GROUP_INFORMATION: u8 = 0x09;
+    pub const ASYMMETRIC_NAMESPACE_ACCESS: u8 = 0x0C;
+    pub const PERSISTENT_EVENT_LOG: u8 = 0x0D;
+    pub const SANITIZE_STATUS: u8 = 0x81;
+}
+
+// little endian fields at arbitrary offsets of a log page
+fn le_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn le_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn le_u128(data: &[u8], offset: usize) -> u128 {
+    u128::from_le_bytes(data[offset..offset + 16].try_into().unwrap())
+}
+
+/// SMART / Health Information log (LID 02h).
+#[derive(Debug, Clone, Copy)]
+pub struct SmartLog {
+    /// Critical Warning bits, see the `*_warning` helpers.
+    pub critical_warning: u8,
+    /// Composite temperature in Kelvin.
+    pub composite_temperature: u16,
+    /// Remaining spare capacity, in percent.
+    pub available_spare: u8,
+    pub available_spare_threshold: u8,
+    /// Vendor estimate of the life used, may exceed 100.
+    pub percentage_used: u8,
+    pub endurance_group_critical_warning: u8,
+    /// In thousands of 512 byte units.
+    pub data_units_read: u128,
+    /// In thousands of 512 byte units.
+    pub data_units_written: u128,
+    pub host_read_commands: u128,
+    pub host_write_commands: u128,
+    /// In minutes.
+    pub controller_busy_time: u128,
+    pub power_cycles: u128,
+    pub power_on_hours: u128,
+    pub unsafe_shutdowns: u128,
+    pub media_errors: u128,
+    pub error_log_entries: u128,
+    /// Minutes above the warning composite temperature threshold.
+    pub warning_temperature_time: u32,
+    /// Minutes above the critical composite temperature threshold.
+    pub critical_temperature_time: u32,
+    /// Temperature sensors 1 to 8 in Kelvin, 0 if not implemented.
+    pub temperature_sensors: [u16; 8],
+}
+
+impl SmartLog {
+    pub const SIZE: usize = 512;
+
+    pub fn parse(data: &[u8]) -> Self {
+        let mut temperature_sensors = [0; 8];
+        for (i, t) in temperature_sensors.iter_mut().enumerate() {
+            *t = le_u16(data, 200 + i * 2);
+        }
+
+        Self {
+            critical_warning: data[0],
+            composite_temperature: le_u16(data, 1),
+            available_spare: data[3],
+            available_spare_threshold: data[4],
+            percentage_used: data[5],
+            endurance_group_critical_warning: data[6],
+            data_units_read: le_u128(data, 32),
+            data_units_written: le_u128(data, 48),
+            host_read_commands: le_u128(data, 64),
+            host_write_commands: le_u128(data, 80),
+            controller_busy_time: le_u128(data, 96),
+            power_cycles: le_u128(data, 112),
+            power_on_hours: le_u128(data, 128),
+            unsafe_shutdowns: le_u128(data, 144),
+            media_errors: le_u128(data, 160),
+            error_log_entries: le_u128(data, 176),
+            warning_temperature_time: le_u32(data, 192),
+            critical_temperature_time: le_u32(data, 196),
+            temperature_sensors,
+        }
+    }
+
+    /// Available spare fell below its threshold.
+    pub fn spare_warning(&self) -> bool {
+        self.critical_warning & 1 != 0
+    }
+
+    /// A temperature is outside its thresholds.
+    pub fn temperature_warning(&self) -> bool {
+        self.critical_warning & (1 << 1) != 0
+    }
+
+    /// Reliability is degraded by media or internal errors.
+    pub fn reliability_warning(&self) -> bool {
+        self.critical_warning & (1 << 2) != 0
+    }
+
+    /// The media was placed in read only mode.
+    pub fn read_only_warning(&self) -> bool {
+        self.critical_warning & (1 << 3) != 0
+    }
+
+    /// The volatile memory backup device failed.
+    pub fn volatile_backup_warning(&self) -> bool {
+        self.critical_warning & (1 << 4) != 0
+    }
+}
+
+/// One entry of the Error Information log (LID 01h).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorLogEntry {
+    /// Unique, increasing number of the error. 0 marks an unused entry.
+    pub error_count: u64,
+    pub sqid: u16,
+    pub cid: u16,
+    /// Status field of the completion, phase tag removed.
+    pub status: u16,
+    /// Byte in bits 7:0 and bit in bits 10:8 of the command dword that
+    /// caused the error, 0xFFFF if not applicable.
+    pub parameter_error_location: u16,
+    /// First LBA that saw the error.
+    pub lba: u64,
+    pub nsid: u32,
+    /// Log page holding vendor specific information, 0 if none.
+    pub vendor_specific_info: u8,
+    pub transport_type: u8,
+    pub command_specific_info: u64,
+    pub transport_type_specific_info: u16,
+}
+
+impl ErrorLogEntry {
+    pub const SIZE: usize = 64;
+
+    pub fn parse(data: &[u8]) -> Self {
+        Self {
+            error_count: le_u64(data, 0),
+            sqid: le_u16(data, 8),
+            cid: le_u16(data, 10),
+            status: le_u16(data, 12) >> 1,
+            parameter_error_location: le_u16(data, 14),
+            lba: le_u64(data, 16),
+            nsid: le_u32(data, 24),
+            vendor_specific_info: data[28],
+            transport_type: data[29],
+            command_specific_info: le_u64(data, 32),
+            transport_type_specific_info: le_u16(data, 40),
+        }
+    }
+
+    /// Status Code Type and Status Code of the failed command.
+    pub fn status(&self) -> Status {
+        Status::new((self.status >> 8 & 0b111) as u8, self.status as u8)
+    }
+}
+
+/// Used entries of an Error Information log, newest first.
+pub(crate) fn parse_error_log(data: &[u8]) -> Vec<ErrorLogEntry> {
+    data.as_chunks::<{ ErrorLogEntry::SIZE }>()
+        .0
+        .iter()
+        .map(|e| ErrorLogEntry::parse(e))
+        .filter(|e| e.error_count != 0)
+        .collect()
+}
+
+/// Outcome of the most recent sanitize operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SanitizeState {
+    NeverSanitized,
+    Completed,
+    InProgress,
+    /// The controller stays in sanitize failure mode until a sanitize
+    /// succeeds or [`crate::Nvme::exit_sanitize_failure`] is called.
+    Failed,
+    /// Completed, but the media was not deallocated as requested.
+    CompletedWithoutDeallocate,
+    Reserved(u8),
+}
+
+/// Sanitize Status log (LID 81h).
+#[derive(Debug, Clone, Copy)]
+pub struct SanitizeStatus {
+    /// Progress of the sanitize in progress, out of 65536.
+    pub progress: u16,
+    pub state: SanitizeState,
+    /// Overwrite passes done by the latest overwrite sanitize.
+    pub overwrite_passes: u8,
+    /// No user data was written since the last sanitize or manufacture.
+    pub global_data_erased: bool,
+    /// Command dword 10 of the latest sanitize.
+    pub cdw10: u32,
+    /// Estimated seconds for overwrite, block and crypto erase, `u32::MAX`
+    /// if not reported.
+    pub estimated_overwrite: u32,
+    pub estimated_block_erase: u32,
+    pub estimated_crypto_erase: u32,
+}
+
+impl SanitizeStatus {
+    pub const SIZE: usize = 512;
+
+    pub fn parse(data: &[u8]) -> Self {
+        let sstat = le_u16(data, 2);
+        let state = match sstat & 0b111 {
+            0 => SanitizeState::NeverSanitized,
+            1 => SanitizeState::Completed,
+            2 => SanitizeState::InProgress,
+            3 => SanitizeState::Failed,
+            4 => SanitizeState::CompletedWithoutDeallocate,
+            s => SanitizeState::Reserved(s as u8),
+        };
+
+        Self {
+            progress: le_u16(data, 0),
+            state,
+            overwrite_passes: (sstat >> 3 & 0x1F) as u8,
+            global_data_erased: sstat & 1 << 8 != 0,
+            cdw10: le_u32(data, 4),
+            estimated_overwrite: le_u32(data, 8),
+            estimated_block_erase: le_u32(data, 12),
+            estimated_crypto_erase: le_u32(data, 16),
+        }
+    }
+
+    /// Progress in percent, only meaningful while in progress.
+    pub fn progress_percent(&self) -> u8 {
+        (self.progress as u32 * 100 / 0x10000) as u8
+    }
+}
@@ -0,0 +1,63 @@
+use crate::format::ProtectionInfo;
+
+/// Settings for [`crate::Nvme::create_namespace`].
+#[derive(Debug, Clone, Copy)]
+pub struct CreateNamespace {
+    /// Size in logical blocks (NSZE).
+    pub size: u64,
+    /// Blocks that may be allocated at once (NCAP), at most `size`. Less
+    /// than `size` thin provisions the namespace, if supported.
+    pub capacity: u64,
+    /// Index into the LBA formats reported by Identify Namespace with
+    /// [`crate::NSID_ALL`].
+    pub lba_format: u8,
+    /// Metadata is sent inline with the data.
+    pub metadata_extended: bool,
+    pub protection: ProtectionInfo,
+    /// May be attached to more than one controller (NMIC bit 0).
+    pub shared: bool,
+}
+
+impl CreateNamespace {
+    // host specified fields of the Identify Namespace data structure
+    pub(crate) const DATA_SIZE: usize = 0x1000;
+
+    pub fn new(size: u64, lba_format: u8) -> Self {
+        Self {
+            size,
+            capacity: size,
+            lba_format,
+            metadata_extended: false,
+            protection: ProtectionInfo::Disabled,
+            shared: false,
+        }
+    }
+
+    pub(crate) fn data(&self) -> [u8; Self::DATA_SIZE] {
+        let mut data = [0; Self::DATA_SIZE];
+        let lbaf = self.lba_format;
+
+        data[0..8].copy_from_slice(&self.size.to_le_bytes());
+        data[8..16].copy_from_slice(&self.capacity.to_le_bytes());
+        // FLBAS
+        data[26] = (lbaf & 0xF) | (self.metadata_extended as u8) << 4 | (lbaf >> 4 & 0b11) << 5;
+        // DPS
+        data[29] = self.protection as u8;
+        // NMIC
+        data[30] = self.shared as u8;
+        data
+    }
+}
+
+// Controller List data structure of Namespace Attachment
+pub(crate) fn controller_list(controllers: &[u16]) -> [u8; CreateNamespace::DATA_SIZE] {
+    let mut data = [0; CreateNamespace::DATA_SIZE];
+    data[0..2].copy_from_slice(&(controllers.len() as u16).to_le_bytes());
+    for (i, id) in controllers.iter().enumerate() {
+        data[2 + i * 2..4 + i * 2].copy_from_slice(&id.to_le_bytes());
+    }
+    data
+}
+
+// most identifiers a Controller List holds
+pub(crate) const MAX_CONTROLLER_LIST: usize = 2047;
@@ -1,47 +1,187 @@
-use core::ptr::NonNull;
+use core::{hint::spin_loop, ptr::NonNull, time::Duration};
 
-use alloc::vec::Vec;
-use dma_api::{DSlice, DSliceMut, DVec, Direction};
-use log::{debug, info};
+use alloc::{sync::Arc, vec::Vec};
+use dma_api::{DVec, Direction};
+use log::{debug, info, warn};
 
 use crate::{
     command::{
         self, ControllerInfo, Feature, Identify, IdentifyActiveNamespaceList, IdentifyController,
-        IdentifyNamespaceDataStructure,
+        IdentifyNamespaceDataStructure, IdentifyNamespaceIdDescriptorList, NamespaceDataStructure,
+        NamespaceIdentifiers,
     },
     err::*,
-    queue::{CommandSet, NvmeQueue},
-    registers::NvmeReg,
+    firmware::{FirmwareSlotLog, FirmwareUpdater},
+    format::{FormatNvm, SecureErase},
+    io_queue::{IoQueue, ReadSegment, SharedQueue},
+    log_page::{
+        parse_error_log, ErrorLogEntry, LogPageId, SanitizeState, SanitizeStatus, SmartLog,
+        NSID_ALL,
+    },
+    namespace::{controller_list, CreateNamespace, MAX_CONTROLLER_LIST},
+    prp::PrpList,
+    queue::{CommandSet, Completion, NvmeQueue, Request},
+    registers::{ControllerStatus, NvmeReg},
+    sanitize::{Sanitize, SanitizeAction},
+    sgl::SglSupport,
+    time::{self, set_time_source, Deadline, TimeSource},
 };
 
 pub struct Nvme {
     bar: NonNull<NvmeReg>,
-    admin_queue: NvmeQueue,
-    io_queues: Vec<NvmeQueue>,
+    admin_queue: Arc<SharedQueue>,
+    io_queues: Vec<IoQueue>,
     num_ns: usize,
+    // OACS bit 3, Namespace Management and Attachment
+    namespace_management: bool,
     sqes: u32,
     cqes: u32,
+    page_size: usize,
+    max_transfer_size: usize,
+    sgl_support: SglSupport,
+    // LPA bit 2, Get Log Page takes an offset
+    log_page_offset: bool,
+    // ELPE + 1
+    error_log_entries: usize,
+    interrupt_mode: InterruptMode,
+    shutdown_timeout: Duration,
+    shutdown_on_drop: Option<ShutdownKind>,
+    shut_down: bool,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct Config {
     pub page_size: usize,
+    /// Clamped to the number of queue pairs the controller allocates.
     pub io_queue_pair_count: usize,
+    /// Entries of the admin submission and completion queue, at most 4096.
+    pub admin_queue_depth: usize,
+    /// Entries of each I/O submission and completion queue. Clamped to the
+    /// CAP.MQES limit of the controller.
+    pub io_queue_depth: usize,
+    pub interrupt_mode: InterruptMode,
+    /// Interrupt vector for the completion queue of each I/O queue pair, by
+    /// index. Queues past the end of the slice are polled.
+    pub io_queue_vectors: &'static [u16],
+    /// How long to wait for an admin command before giving up.
+    pub admin_timeout: Duration,
+    /// How long to wait for an I/O command before giving up.
+    pub io_timeout: Duration,
+    /// Shutdown performed when the [`Nvme`] is dropped, `None` to leave the
+    /// controller running.
+    pub shutdown_on_drop: Option<ShutdownKind>,
+    /// Attach the Error Information log entry of a failed synchronous
+    /// command to its [`CommandError`]. Costs an extra admin command per
+    /// failure.
+    pub capture_error_log: bool,
+    /// Clock bounding every wait on the controller, installed with
+    /// [`set_time_source`]. Without one, and none installed before, a hung
+    /// controller blocks its caller forever.
+    pub time_source: Option<&'static dyn TimeSource>,
+    /// Called when a command could not be aborted, or the controller
+    /// failed, so the controller was disabled and every queue is stalled
+    /// until [`Nvme::recover`] runs. May be called from any context that
+    /// waits on a command, including [`IoQueue`] handles and futures, so it
+    /// should only schedule the recovery.
+    pub on_reset_required: Option<fn()>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            page_size: 0x1000,
+            io_queue_pair_count: 1,
+            admin_queue_depth: 64,
+            io_queue_depth: 64,
+            interrupt_mode: InterruptMode::Polled,
+            io_queue_vectors: &[],
+            admin_timeout: Duration::from_secs(60),
+            io_timeout: Duration::from_secs(30),
+            shutdown_on_drop: Some(ShutdownKind::Normal),
+            capture_error_log: false,
+            time_source: None,
+            on_reset_required: None,
+        }
+    }
+}
+
+/// How the controller signals completions to the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptMode {
+    /// No interrupts, completion queues are polled.
+    Polled,
+    /// Pin based (INTx) interrupts, everything on vector 0. Other vectors
+    /// in [`Config::io_queue_vectors`] are rejected.
+    Pin,
+    /// Single or multiple message MSI.
+    Msi,
+    MsiX,
+}
+
+/// How [`Nvme::shutdown`] notifies the controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownKind {
+    /// Orderly shutdown, the volatile write cache is flushed to media.
+    Normal,
+    /// Power is about to be lost, the controller does as little as it can.
+    Abrupt,
 }
 
+// every Identify data structure is this long
+const IDENTIFY_DATA_SIZE: usize = 0x1000;
+
+// largest piece of a log page read with one command
+const MAX_LOG_PAGE_CHUNK: usize = 0x10_0000;
+
+// AQA.ASQS and AQA.ACQS are 12 bit, 0's based
+const MAX_ADMIN_QUEUE_DEPTH: usize = 4096;
+
+// used when the controller does not report RTD3E
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl Nvme {
     pub fn new(bar: NonNull<u8>, config: Config) -> Result<Self> {
-        let admin_queue = NvmeQueue::new(0, bar.cast(), config.page_size, 64, 64)?;
+        if let Some(source) = config.time_source {
+            set_time_source(source);
+        }
+        time::warn_if_unset();
+
+        check_page_size(unsafe { bar.cast().as_ref() }, config.page_size)?;
+
+        let admin_depth = queue_depth(config.admin_queue_depth, MAX_ADMIN_QUEUE_DEPTH)?;
+        let mut admin_queue =
+            NvmeQueue::new(0, bar.cast(), config.page_size, admin_depth, admin_depth)?;
+        admin_queue.timeout = config.admin_timeout;
 
         assert!(config.io_queue_pair_count > 0);
+        if config.interrupt_mode == InterruptMode::Pin
+            && config.io_queue_vectors.iter().any(|&v| v != 0)
+        {
+            return Err(Error::InvalidParameter(
+                "pin based interrupts only have vector 0",
+            ));
+        }
+
+        let admin_queue = SharedQueue::new(admin_queue, config.on_reset_required);
+        admin_queue.set_capture_error_log(config.capture_error_log);
 
         let mut s = Self {
             bar: bar.cast(),
-            admin_queue,
+            admin_queue: Arc::new(admin_queue),
             io_queues: Vec::new(),
             num_ns: 0,
+            namespace_management: false,
             sqes: 6,
             cqes: 4,
+            page_size: config.page_size,
+            max_transfer_size: usize::MAX,
+            sgl_support: SglSupport::default(),
+            log_page_offset: false,
+            error_log_entries: 1,
+            interrupt_mode: config.interrupt_mode,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            shutdown_on_drop: config.shutdown_on_drop,
+            shut_down: false,
         };
 
         let version = s.version();
@@ -51,21 +191,25 @@ impl Nvme {
             version.0, version.1, version.2
         );
 
-        s.init(config)?;
+        if let Err(e) = s.init(config) {
+            // nothing to shut down on a controller that never came up
+            s.shutdown_on_drop = None;
+            return Err(e);
+        }
 
         Ok(s)
     }
 
-    fn reset(&mut self) {
-        self.reg().reset();
+    fn reset(&mut self) -> Result {
+        self.reg().reset()
     }
 
     fn reset_and_setup_controller_info(&mut self) -> Result<ControllerInfo> {
-        self.reset();
+        self.reset()?;
 
         self.nvme_configure_admin_queue();
 
-        self.reg().ready_for_read_controller_info();
+        self.reg().ready_for_read_controller_info(self.page_size)?;
 
         self.get_identfy(IdentifyController::new())
     }
@@ -78,11 +222,11 @@ impl Nvme {
         self.sqes = controller.sqes_min as _;
         self.cqes = controller.cqes_min as _;
 
-        self.reset();
+        self.reset()?;
 
         self.nvme_configure_admin_queue();
 
-        self.reg().setup_cc(self.sqes, self.cqes);
+        self.reg().setup_cc(self.sqes, self.cqes, self.page_size)?;
 
         let controller = self.get_identfy(IdentifyController::new())?;
 
@@ -90,18 +234,46 @@ impl Nvme {
 
         self.num_ns = controller.number_of_namespaces as _;
 
+        if controller.max_data_transfer_size > 0 {
+            // a limit too large to represent is no limit
+            self.max_transfer_size = 1usize
+                .checked_shl(controller.max_data_transfer_size as u32)
+                .and_then(|n| n.checked_mul(self.reg().min_page_size()))
+                .unwrap_or(usize::MAX);
+        }
+        self.sgl_support = controller.sgl_support;
+        self.log_page_offset = controller.lpa & (1 << 2) != 0;
+        self.namespace_management = controller.oacs & (1 << 3) != 0;
+        self.error_log_entries = controller.error_log_page_entries as usize + 1;
+        if controller.rtd3_entry_latency > 0 {
+            self.shutdown_timeout = Duration::from_micros(controller.rtd3_entry_latency as _);
+        }
+
         self.config_io_queue(config)?;
 
         debug!("IO queue ok.");
+        self.wait_for_namespace()?;
+        debug!("Namespace ok.");
+        Ok(())
+    }
+
+    fn wait_for_namespace(&mut self) -> Result {
+        // namespaces are up to the host, there may be none yet
+        if self.namespace_management {
+            return Ok(());
+        }
+        let deadline = Deadline::after(self.admin_queue.with(|q| q.timeout));
         loop {
             let ns = self.get_identfy(IdentifyNamespaceDataStructure::new(1))?;
             if let Some(ns) = ns {
                 debug!("Namespace: {:?}", ns);
-                break;
+                return Ok(());
+            }
+            if deadline.expired() {
+                warn!("namespace 1 did not become active");
+                return Err(Error::Timeout);
             }
         }
-        debug!("Namespace ok.");
-        Ok(())
     }
 
     pub fn namespace_list(&mut self) -> Result<Vec<Namespace>> {
@@ -109,93 +281,595 @@ impl Nvme {
         let mut out = Vec::new();
 
         for id in id_list {
-            let ns = self
-                .get_identfy(IdentifyNamespaceDataStructure::new(id))?
-                .unwrap();
-
-            out.push(Namespace {
-                id,
-                lba_size: ns.lba_size as _,
-                lba_count: ns.namespace_size as _,
-                metadata_size: ns.metadata_size as _,
-            });
+            let Some(ns) = self.get_identfy(IdentifyNamespaceDataStructure::new(id))? else {
+                continue;
+            };
+            // no block size to do I/O with
+            if ns.lba_format().is_none() {
+                warn!("namespace {id}: formatted LBA format out of range, skipped");
+                continue;
+            }
+
+            out.push(Namespace::new(id, &ns));
         }
 
         Ok(out)
     }
 
+    /// Identify Namespace data of `nsid`, `None` if it is not active.
+    pub fn identify_namespace(&mut self, nsid: u32) -> Result<Option<NamespaceDataStructure>> {
+        self.get_identfy(IdentifyNamespaceDataStructure::new(nsid))
+    }
+
+    /// EUI-64, NGUID, UUID and command set of `nsid`.
+    pub fn namespace_identifiers(&mut self, nsid: u32) -> Result<NamespaceIdentifiers> {
+        self.get_identfy(IdentifyNamespaceIdDescriptorList::new(nsid))
+    }
+
+    /// The active namespace with the given UUID, if any.
+    pub fn find_namespace_by_uuid(&mut self, uuid: &[u8; 16]) -> Result<Option<Namespace>> {
+        for ns in self.namespace_list()? {
+            if self.namespace_identifiers(ns.id)?.uuid.as_ref() == Some(uuid) {
+                return Ok(Some(ns));
+            }
+        }
+        Ok(None)
+    }
+
     // config admin queue
     // 1. set admin queue(cq && sq) size
     // 2. set admin queue(cq && sq) dma address
     // 3. enable ctrl
     fn nvme_configure_admin_queue(&mut self) {
-        self.reg().set_admin_submission_and_completion_queue_size(
-            self.admin_queue.sq.len(),
-            self.admin_queue.cq.len(),
-        );
+        let reg = self.reg();
+        self.admin_queue.with(|q| {
+            reg.set_admin_submission_and_completion_queue_size(q.sq.len(), q.cq.len());
+            reg.set_admin_submission_queue_base_address(q.sq.bus_addr());
+            reg.set_admin_completion_queue_base_address(q.cq.bus_addr());
+        });
+    }
 
-        self.reg()
-            .set_admin_submission_queue_base_address(self.admin_queue.sq.bus_addr());
+    fn config_io_queue(&mut self, config: Config) -> Result {
+        let mut num = config.io_queue_pair_count;
+        let allocated = self.set_number_of_queues(num)?;
+        if allocated < num {
+            warn!("{num} I/O queue pairs exceed the controller limit, using {allocated}");
+            num = allocated;
+        }
 
-        self.reg()
-            .set_admin_completion_queue_base_address(self.admin_queue.cq.bus_addr());
+        let depth = queue_depth(config.io_queue_depth, self.reg().max_queue_entries())?;
+        // queues are always allocated in one physically contiguous piece
+        let contiguous = self.reg().contiguous_queues_required();
+
+        for i in 0..num {
+            let id = (i + 1) as u32;
+            let mut io_queue = NvmeQueue::new(id, self.bar, config.page_size, depth, depth)
+                .inspect_err(|e| {
+                    if contiguous {
+                        warn!("controller requires contiguous queues of {depth} entries: {e:?}");
+                    }
+                })?;
+
+            io_queue.timeout = config.io_timeout;
+            if config.interrupt_mode != InterruptMode::Polled {
+                io_queue.interrupt_vector = config.io_queue_vectors.get(i).copied();
+            }
+
+            self.create_io_queue_pair(&io_queue)?;
+
+            self.io_queues.push(IoQueue::new(
+                io_queue,
+                self.admin_queue.clone(),
+                self.page_size,
+                self.max_transfer_size,
+                self.sgl_support,
+                config.on_reset_required,
+            ));
+        }
+
+        Ok(())
     }
 
-    fn config_io_queue(&mut self, config: Config) -> Result {
-        let num = config.io_queue_pair_count;
+    // returns how many queue pairs the controller allocated, which may be
+    // fewer than asked for
+    fn set_number_of_queues(&self, num: usize) -> Result<usize> {
         // 设置 io queue 数量
         let cmd = CommandSet::set_features(Feature::NumberOfQueues {
             nsq: num as u32 - 1,
             ncq: num as u32 - 1,
         });
-        self.admin_queue.command_sync(cmd)?;
+        let dw0 = self.admin_queue.command_sync(cmd)?;
+        // NSQA and NCQA, both 0's based
+        let nsqa = (dw0 & 0xFFFF) as usize + 1;
+        let ncqa = (dw0 >> 16) as usize + 1;
+        Ok(nsqa.min(ncqa))
+    }
 
-        for i in 0..num {
-            let id = (i + 1) as u32;
-            let io_queue = NvmeQueue::new(
-                id,
-                self.bar,
-                config.page_size,
-                2usize.pow(self.sqes as _),
-                2usize.pow(self.cqes as _),
-            )?;
-
-            let data = CommandSet::create_io_completion_queue(
-                io_queue.qid,
-                io_queue.cq.len() as _,
-                io_queue.cq.bus_addr(),
-                true,
-                false,
-                0,
-            );
-            self.admin_queue.command_sync(data)?;
-
-            let data = CommandSet::create_io_submission_queue(
-                io_queue.qid,
-                io_queue.sq.len() as _,
-                io_queue.sq.bus_addr(),
-                true,
-                0,
-                io_queue.qid,
-                0,
+    fn create_io_queue_pair(&self, io_queue: &NvmeQueue) -> Result {
+        let data = CommandSet::create_io_completion_queue(
+            io_queue.qid,
+            io_queue.cq.len() as _,
+            io_queue.cq.bus_addr(),
+            true,
+            io_queue.interrupt_vector.is_some(),
+            io_queue.interrupt_vector.unwrap_or(0) as _,
+        );
+        self.admin_queue.command_sync(data)?;
+
+        let data = CommandSet::create_io_submission_queue(
+            io_queue.qid,
+            io_queue.sq.len() as _,
+            io_queue.sq.bus_addr(),
+            true,
+            0,
+            io_queue.qid,
+            0,
+        );
+        self.admin_queue.command_sync(data)?;
+
+        Ok(())
+    }
+
+    /// Whether the controller reported a fatal error, or a command timed out
+    /// and could not be aborted, leaving the controller in need of a reset.
+    pub fn reset_required(&self) -> bool {
+        self.reg().controller_status().fatal
+            || self.admin_queue.reset_required()
+            || self.io_queues.iter().any(|q| q.shared().reset_required())
+    }
+
+    /// Reset the controller if [`Nvme::reset_required`], returning whether
+    /// it did.
+    ///
+    /// The I/O queues are recreated under their old identifiers and every
+    /// outstanding command is issued again, except the ones that timed out,
+    /// whose callers already got [`Error::Timeout`]. The controller was
+    /// disabled when those were given up on, so it no longer accesses their
+    /// buffers.
+    ///
+    /// The sync I/O methods of [`Nvme`] call this themselves. Users of
+    /// [`IoQueue`] handles or futures learn about it through
+    /// [`Config::on_reset_required`].
+    pub fn recover(&mut self) -> Result<bool> {
+        if !self.reset_required() {
+            return Ok(false);
+        }
+        warn!("resetting controller to recover from a failure");
+        self.reset_controller()?;
+        Ok(true)
+    }
+
+    /// Disable and re-enable the controller, e.g. for error recovery or on
+    /// resume from suspend, returning the active namespaces afterwards.
+    ///
+    /// Every I/O queue pair is recreated with its old identifier and depth,
+    /// so [`IoQueue`] handles stay usable, and outstanding commands are
+    /// issued again. A [`Namespace`] obtained before stays valid as long as
+    /// it shows up unchanged in the returned list.
+    pub fn reset_controller(&mut self) -> Result<Vec<Namespace>> {
+        self.restart_queues()?;
+        self.wait_for_namespace()?;
+
+        let controller = self.get_identfy(IdentifyController::new())?;
+        self.num_ns = controller.number_of_namespaces as _;
+
+        let namespaces = self.namespace_list()?;
+        info!("controller reset, {} namespace(s)", namespaces.len());
+        Ok(namespaces)
+    }
+
+    /// Reset the whole NVM subsystem, a harder reset than
+    /// [`Nvme::reset_controller`], then bring the controller up again the
+    /// same way.
+    ///
+    /// Returns CSTS.NSSRO, whether the controller saw the subsystem reset.
+    pub fn subsystem_reset(&mut self) -> Result<bool> {
+        if !self.reg().subsystem_reset_supported() {
+            return Err(Error::NotSupported("NVM subsystem reset"));
+        }
+
+        self.reg().take_subsystem_reset_occurred();
+        warn!("NVM subsystem reset");
+        self.reg().subsystem_reset();
+
+        self.reset_controller()?;
+
+        let occurred = self.reg().take_subsystem_reset_occurred();
+        if !occurred {
+            warn!("NSSRO not set after NVM subsystem reset");
+        }
+        Ok(occurred)
+    }
+
+    /// Shut the controller down before power is removed.
+    ///
+    /// The I/O queues are deleted, then CC.SHN is set and the shutdown waited
+    /// for within the RTD3 entry latency of the controller. No command may be
+    /// issued afterwards, except through [`Nvme::reset_controller`].
+    pub fn shutdown(&mut self, kind: ShutdownKind) -> Result {
+        for io_queue in self.io_queues.iter().rev() {
+            let qid = io_queue.qid() as u32;
+            let r = self
+                .admin_queue
+                .command_sync(CommandSet::delete_io_submission_queue(qid))
+                .and_then(|_| {
+                    self.admin_queue
+                        .command_sync(CommandSet::delete_io_completion_queue(qid))
+                });
+            if let Err(e) = r {
+                warn!("failed to delete I/O queue {qid}: {e:?}");
+            }
+            // hand out the completions of commands aborted by the deletion
+            io_queue.shared().with(|q| {
+                q.drain();
+                q.set_deleted(true);
+            });
+        }
+
+        self.reg()
+            .shutdown(kind == ShutdownKind::Abrupt, self.shutdown_timeout)?;
+        self.shut_down = true;
+        info!("NVME shutdown ({kind:?}) complete");
+        Ok(())
+    }
+
+    fn restart_queues(&mut self) -> Result {
+        self.reset()?;
+        self.shut_down = false;
+
+        // the controller forgot every queue when it was disabled
+        self.admin_queue.with(|q| q.reset_rings());
+        for io_queue in &self.io_queues {
+            io_queue.shared().with(|q| {
+                q.reset_rings();
+                q.set_deleted(false);
+            });
+        }
+
+        self.nvme_configure_admin_queue();
+        self.reg().setup_cc(self.sqes, self.cqes, self.page_size)?;
+        self.admin_queue.with(|q| q.resubmit());
+        self.admin_queue.clear_reset_required();
+
+        if self.set_number_of_queues(self.io_queues.len())? < self.io_queues.len() {
+            return Err(Error::NotSupported(
+                "controller allocated fewer I/O queues after reset",
+            ));
+        }
+        for io_queue in &self.io_queues {
+            let shared = io_queue.shared();
+            shared.with(|q| {
+                self.create_io_queue_pair(q)?;
+                q.resubmit();
+                Ok::<_, Error>(())
+            })?;
+            shared.clear_reset_required();
+        }
+
+        Ok(())
+    }
+
+    // reset the controller after a sync command that could not be aborted
+    fn recover_after<T>(&mut self, r: Result<T>) -> Result<T> {
+        if let Err(Error::Timeout | Error::ControllerFatal) = r {
+            self.recover()?;
+        }
+        r
+    }
+
+    /// Identify Controller data, decoded.
+    pub fn identify_controller(&mut self) -> Result<ControllerInfo> {
+        self.get_identfy(IdentifyController::new())
+    }
+
+    /// Read `len` bytes of log page `lid`, starting `offset` bytes in.
+    ///
+    /// Logs larger than one transfer are read in pieces, which needs offset
+    /// support from the controller. `nsid` is [`NSID_ALL`] for logs that are
+    /// not namespace specific.
+    pub fn get_log_page(&mut self, lid: u8, nsid: u32, offset: u64, len: usize) -> Result<Vec<u8>> {
+        if len == 0 || !len.is_multiple_of(4) || !offset.is_multiple_of(4) {
+            return Err(Error::InvalidParameter(
+                "log page length and offset must be dword multiples",
+            ));
+        }
+
+        let chunk = self.max_transfer_size.min(MAX_LOG_PAGE_CHUNK) & !3;
+        if (offset > 0 || len > chunk) && !self.log_page_offset {
+            return Err(Error::NotSupported("log page offset"));
+        }
+
+        let buff = DVec::<u8>::zeros(
+            u64::MAX,
+            chunk.min(len),
+            self.page_size,
+            Direction::FromDevice,
+        )
+        .map_err(|_| Error::NoMemory)?;
+        let mut out = Vec::with_capacity(len);
+
+        while out.len() < len {
+            let n = chunk.min(len - out.len());
+            let prp = PrpList::new(buff.bus_addr(), n, self.page_size)?;
+            let cmd = CommandSet::get_log_page(
+                lid,
+                nsid,
+                offset + out.len() as u64,
+                (n / 4) as u32,
+                &prp,
             );
+            self.admin_queue.command_sync(cmd)?;
+
+            out.extend_from_slice(&buff.as_ref()[..n]);
+        }
 
-            self.admin_queue.command_sync(data)?;
+        Ok(out)
+    }
+
+    /// SMART / Health Information of namespace `nsid`, or of the whole
+    /// controller for [`NSID_ALL`].
+    pub fn smart_log(&mut self, nsid: u32) -> Result<SmartLog> {
+        let data = self.get_log_page(LogPageId::SMART_HEALTH, nsid, 0, SmartLog::SIZE)?;
+        Ok(SmartLog::parse(&data))
+    }
+
+    /// Every used entry of the Error Information log, newest first.
+    pub fn error_log(&mut self) -> Result<Vec<ErrorLogEntry>> {
+        let len = self.error_log_entries * ErrorLogEntry::SIZE;
+        let data = self.get_log_page(LogPageId::ERROR_INFORMATION, NSID_ALL, 0, len)?;
+        Ok(parse_error_log(&data))
+    }
+
+    /// See [`Config::capture_error_log`].
+    pub fn set_capture_error_log(&self, capture: bool) {
+        self.admin_queue.set_capture_error_log(capture);
+    }
 
-            self.io_queues.push(io_queue);
+    /// Format namespace `nsid`, or every namespace with [`NSID_ALL`], and
+    /// return the namespace list in its new format.
+    ///
+    /// Data on the formatted namespaces is lost. Some controllers format
+    /// all namespaces together no matter `nsid` (FNA bit 0).
+    pub fn format_nvm(&mut self, nsid: u32, format: FormatNvm) -> Result<Vec<Namespace>> {
+        let controller = self.identify_controller()?;
+        if controller.oacs & 1 << 1 == 0 {
+            return Err(Error::NotSupported("format NVM"));
         }
+        if format.secure_erase == SecureErase::Cryptographic && controller.fna & 1 << 2 == 0 {
+            return Err(Error::NotSupported("cryptographic erase"));
+        }
+
+        if nsid != NSID_ALL {
+            let ns = self
+                .identify_namespace(nsid)?
+                .ok_or(Error::InvalidParameter("namespace is not active"))?;
+            if format.lba_format as usize >= ns.lba_formats.len() {
+                return Err(Error::InvalidParameter("no such LBA format"));
+            }
+        }
+
+        let cmd = CommandSet::format_nvm(nsid, format.cdw10());
+        self.admin_queue.command_sync_timeout(cmd, format.timeout)?;
+
+        self.namespace_list()
+    }
 
+    /// Start a sanitize of the whole NVM subsystem.
+    ///
+    /// Returns once the controller accepted it, follow it with
+    /// [`Nvme::wait_sanitize`]. All user data is lost, and most commands fail
+    /// until the sanitize finishes.
+    pub fn sanitize(&mut self, sanitize: Sanitize) -> Result {
+        let controller = self.identify_controller()?;
+        if controller.sanicap & sanitize.action.capability() == 0 {
+            return Err(Error::NotSupported("sanitize action"));
+        }
+        // NDI, deallocation cannot be skipped
+        if sanitize.no_deallocate && controller.sanicap & 1 << 29 != 0 {
+            return Err(Error::NotSupported("sanitize without deallocate"));
+        }
+        if let SanitizeAction::Overwrite { passes, .. } = sanitize.action {
+            if !(1..=16).contains(&passes) {
+                return Err(Error::InvalidParameter("overwrite passes must be 1 to 16"));
+            }
+        }
+
+        let cmd = CommandSet::sanitize(sanitize.cdw10(), sanitize.cdw11());
+        self.admin_queue.command_sync(cmd)?;
         Ok(())
     }
 
+    /// Sanitize Status log.
+    pub fn sanitize_status(&mut self) -> Result<SanitizeStatus> {
+        let data = self.get_log_page(
+            LogPageId::SANITIZE_STATUS,
+            NSID_ALL,
+            0,
+            SanitizeStatus::SIZE,
+        )?;
+        Ok(SanitizeStatus::parse(&data))
+    }
+
+    /// Read the Sanitize Status log every `interval` until the sanitize is
+    /// no longer in progress, passing each status read to `progress`.
+    ///
+    /// The final status is returned whether the sanitize succeeded or not,
+    /// see [`SanitizeState`]. Needs a time source, see [`set_time_source`].
+    pub fn wait_sanitize(
+        &mut self,
+        timeout: Duration,
+        interval: Duration,
+        mut progress: impl FnMut(&SanitizeStatus),
+    ) -> Result<SanitizeStatus> {
+        if !time::is_set() {
+            return Err(Error::NotSupported(
+                "waiting for a sanitize without a time source",
+            ));
+        }
+
+        let deadline = Deadline::after(timeout);
+        loop {
+            let status = self.sanitize_status()?;
+            if status.state != SanitizeState::InProgress {
+                return Ok(status);
+            }
+            progress(&status);
+            if deadline.expired() {
+                return Err(Error::Timeout);
+            }
+
+            // the admin queue is free for others in between
+            let next = Deadline::after(interval);
+            while !next.expired() {
+                spin_loop();
+            }
+        }
+    }
+
+    /// Leave sanitize failure mode after a failed sanitize started with
+    /// [`Sanitize::allow_unrestricted_exit`].
+    pub fn exit_sanitize_failure(&mut self) -> Result {
+        // SANACT 001b, Exit Failure Mode
+        self.admin_queue
+            .command_sync(CommandSet::sanitize(0b001, 0))?;
+        Ok(())
+    }
+
+    /// Create a namespace and return its NSID.
+    ///
+    /// The new namespace is not active until attached with
+    /// [`Nvme::attach_namespace`].
+    pub fn create_namespace(&mut self, create: CreateNamespace) -> Result<u32> {
+        self.check_namespace_management()?;
+        if create.size == 0 {
+            return Err(Error::InvalidParameter("namespace size must not be 0"));
+        }
+        if create.capacity > create.size {
+            return Err(Error::InvalidParameter(
+                "namespace capacity must be at most its size",
+            ));
+        }
+
+        let mut buff = DVec::zeros(
+            u64::MAX,
+            CreateNamespace::DATA_SIZE,
+            self.page_size,
+            Direction::ToDevice,
+        )
+        .map_err(|_| Error::NoMemory)?;
+        buff.copy_from_slice(&create.data());
+        let prp = PrpList::new(buff.bus_addr(), CreateNamespace::DATA_SIZE, self.page_size)?;
+
+        let nsid = self
+            .admin_queue
+            .command_sync(CommandSet::create_namespace(&prp))?;
+        info!("created namespace {nsid}");
+        Ok(nsid)
+    }
+
+    /// Delete namespace `nsid`, or all of them with [`NSID_ALL`], and return
+    /// the active namespaces afterwards.
+    pub fn delete_namespace(&mut self, nsid: u32) -> Result<Vec<Namespace>> {
+        self.check_namespace_management()?;
+        self.admin_queue
+            .command_sync(CommandSet::delete_namespace(nsid))?;
+        info!("deleted namespace {nsid:#x}");
+        self.namespace_list()
+    }
+
+    /// Attach namespace `nsid` to the controllers in `controllers`, and
+    /// return the active namespaces afterwards.
+    ///
+    /// This controller is [`ControllerInfo::controller_id`], the others of
+    /// the subsystem come from [`crate::IdentifyControllerList`].
+    pub fn attach_namespace(&mut self, nsid: u32, controllers: &[u16]) -> Result<Vec<Namespace>> {
+        self.namespace_attachment(nsid, 0, controllers)
+    }
+
+    /// Detach namespace `nsid` from the controllers in `controllers`, and
+    /// return the active namespaces afterwards.
+    pub fn detach_namespace(&mut self, nsid: u32, controllers: &[u16]) -> Result<Vec<Namespace>> {
+        self.namespace_attachment(nsid, 1, controllers)
+    }
+
+    fn namespace_attachment(
+        &mut self,
+        nsid: u32,
+        sel: u32,
+        controllers: &[u16],
+    ) -> Result<Vec<Namespace>> {
+        self.check_namespace_management()?;
+        if controllers.is_empty() || controllers.len() > MAX_CONTROLLER_LIST {
+            return Err(Error::InvalidParameter(
+                "controller list must hold 1 to 2047 entries",
+            ));
+        }
+
+        let mut buff = DVec::zeros(
+            u64::MAX,
+            CreateNamespace::DATA_SIZE,
+            self.page_size,
+            Direction::ToDevice,
+        )
+        .map_err(|_| Error::NoMemory)?;
+        buff.copy_from_slice(&controller_list(controllers));
+        let prp = PrpList::new(buff.bus_addr(), CreateNamespace::DATA_SIZE, self.page_size)?;
+
+        self.admin_queue
+            .command_sync(CommandSet::namespace_attachment(nsid, sel, &prp))?;
+        self.namespace_list()
+    }
+
+    fn check_namespace_management(&self) -> Result {
+        if !self.namespace_management {
+            return Err(Error::NotSupported("namespace management"));
+        }
+        Ok(())
+    }
+
+    /// Start a firmware update, see [`FirmwareUpdater`].
+    pub fn firmware_updater(&mut self) -> Result<FirmwareUpdater<'_>> {
+        let controller = self.identify_controller()?;
+        FirmwareUpdater::new(self, &controller)
+    }
+
+    /// Firmware Slot Information log.
+    pub fn firmware_slot_log(&mut self) -> Result<FirmwareSlotLog> {
+        let data =
+            self.get_log_page(LogPageId::FIRMWARE_SLOT, NSID_ALL, 0, FirmwareSlotLog::SIZE)?;
+        Ok(FirmwareSlotLog::parse(&data))
+    }
+
+    pub(crate) fn admin_command_sync(&mut self, cmd: CommandSet) -> Result<u32> {
+        self.admin_queue.command_sync(cmd)
+    }
+
+    pub(crate) fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    pub(crate) fn max_transfer_size(&self) -> usize {
+        self.max_transfer_size
+    }
+
+    /// Issue an Identify command and decode its data, see [`Identify`].
     pub fn get_identfy<T: Identify>(&mut self, mut want: T) -> Result<T::Output> {
         let cmd = want.command_set_mut();
 
         cmd.cdw0 = CommandSet::cdw0_from_opcode(command::Opcode::IDENTIFY);
-        cmd.cdw10 = T::CNS;
+        // keep CNTID in the upper half
+        cmd.cdw10 = cmd.cdw10 & !0xFF | T::CNS;
 
-        let buff = DVec::zeros(u64::MAX, 0x1000, 0x1000, Direction::FromDevice).unwrap();
-        cmd.prp1 = buff.bus_addr();
+        let buff = DVec::zeros(
+            u64::MAX,
+            IDENTIFY_DATA_SIZE,
+            self.page_size,
+            Direction::FromDevice,
+        )
+        .map_err(|_| Error::NoMemory)?;
+        let prp = PrpList::new(buff.bus_addr(), IDENTIFY_DATA_SIZE, self.page_size)?;
+        cmd.prp1 = prp.prp1;
+        cmd.prp2 = prp.prp2;
 
         self.admin_queue.command_sync(*cmd)?;
 
@@ -209,45 +883,189 @@ impl Nvme {
         block_start: u64,
         buff: &[u8],
     ) -> Result<()> {
-        assert!(
-            buff.len().is_multiple_of(ns.lba_size),
-            "buffer size must be multiple of lba size"
-        );
+        let r = self.io_queues[0].block_write_sync(ns, block_start, buff);
+        self.recover_after(r)
+    }
 
-        let buff = DSlice::from(buff, Direction::Bidirectional);
+    pub fn block_read_sync(
+        &mut self,
+        ns: &Namespace,
+        block_start: u64,
+        buff: &mut [u8],
+    ) -> Result<()> {
+        let r = self.io_queues[0].block_read_sync(ns, block_start, buff);
+        self.recover_after(r)
+    }
+
+    /// See [`IoQueue::block_write_vectored_sync`].
+    pub fn block_write_vectored_sync(
+        &mut self,
+        ns: &Namespace,
+        block_start: u64,
+        buffs: &[&[u8]],
+    ) -> Result<()> {
+        let r = self.io_queues[0].block_write_vectored_sync(ns, block_start, buffs);
+        self.recover_after(r)
+    }
 
-        let blk_num = buff.len() / ns.lba_size;
+    /// See [`IoQueue::block_read_vectored_sync`].
+    pub fn block_read_vectored_sync(
+        &mut self,
+        ns: &Namespace,
+        block_start: u64,
+        segments: &mut [ReadSegment<'_>],
+    ) -> Result<()> {
+        let r = self.io_queues[0].block_read_vectored_sync(ns, block_start, segments);
+        self.recover_after(r)
+    }
 
-        let cmd = CommandSet::nvm_cmd_write(ns.id, buff.bus_addr(), block_start, blk_num as _);
+    /// See [`IoQueue::read`].
+    ///
+    /// # Safety
+    ///
+    /// Same as [`IoQueue::read`].
+    pub async unsafe fn read(
+        &self,
+        ns: &Namespace,
+        block_start: u64,
+        buff: &mut [u8],
+    ) -> Result<()> {
+        unsafe { self.io_queues[0].read(ns, block_start, buff) }.await
+    }
 
-        self.io_queues[0].command_sync(cmd)?;
+    /// See [`IoQueue::write`].
+    ///
+    /// # Safety
+    ///
+    /// Same as [`IoQueue::write`].
+    pub async unsafe fn write(&self, ns: &Namespace, block_start: u64, buff: &[u8]) -> Result<()> {
+        unsafe { self.io_queues[0].write(ns, block_start, buff) }.await
+    }
 
-        Ok(())
+    /// See [`IoQueue::flush`].
+    pub async fn flush(&self, ns: &Namespace) -> Result<()> {
+        self.io_queues[0].flush(ns).await
     }
 
-    pub fn block_read_sync(
+    /// See [`IoQueue::submit_read`].
+    ///
+    /// # Safety
+    ///
+    /// Same as [`IoQueue::submit_read`].
+    pub unsafe fn submit_read(
         &mut self,
         ns: &Namespace,
         block_start: u64,
         buff: &mut [u8],
-    ) -> Result<()> {
-        assert!(
-            buff.len() % ns.lba_size == 0,
-            "buffer size must be multiple of lba size"
-        );
+    ) -> Result<Request> {
+        unsafe { self.io_queues[0].submit_read(ns, block_start, buff) }
+    }
 
-        let buff = DSliceMut::from(buff, Direction::FromDevice);
+    /// See [`IoQueue::submit_write`].
+    ///
+    /// # Safety
+    ///
+    /// Same as [`IoQueue::submit_write`].
+    pub unsafe fn submit_write(
+        &mut self,
+        ns: &Namespace,
+        block_start: u64,
+        buff: &[u8],
+    ) -> Result<Request> {
+        unsafe { self.io_queues[0].submit_write(ns, block_start, buff) }
+    }
 
-        let blk_num = buff.len() / ns.lba_size;
+    /// See [`IoQueue::submit_flush`].
+    pub fn submit_flush(&mut self, ns: &Namespace) -> Result<Request> {
+        self.io_queues[0].submit_flush(ns)
+    }
 
-        let cmd = CommandSet::nvm_cmd_read(ns.id, buff.bus_addr(), block_start, blk_num as _);
+    /// Collect every command finished on the first I/O queue since the last call.
+    pub fn poll_completions(&mut self) -> Vec<Completion> {
+        self.io_queues[0].poll_completions()
+    }
 
-        self.io_queues[0].command_sync(cmd)?;
+    /// Number of I/O queue pairs created.
+    pub fn io_queue_count(&self) -> usize {
+        self.io_queues.len()
+    }
+
+    /// A handle to the I/O queue pair at `index`, to be used from e.g. a
+    /// dedicated CPU. The plain I/O methods of [`Nvme`] use the first one.
+    ///
+    /// After [`Nvme::shutdown`] or once the [`Nvme`] is dropped, commands on
+    /// the handle fail with [`Error::QueueDeleted`].
+    pub fn io_queue(&self, index: usize) -> Option<IoQueue> {
+        self.io_queues.get(index).map(|q| q.handle())
+    }
 
-        buff.preper_read_all();
+    /// Drain every I/O completion queue bound to `vector`, waking the tasks
+    /// whose commands finished. Call it from the interrupt handler.
+    ///
+    /// In pin and MSI mode the vector is masked through INTMS while the
+    /// queues are drained, and unmasked through INTMC afterwards.
+    pub fn handle_irq(&self, vector: u16) {
+        let masked = self.check_interrupt_mask(vector).is_ok();
+        if masked {
+            self.reg().mask_interrupt(vector);
+        }
+
+        for queue in &self.io_queues {
+            if queue.interrupt_vector() == Some(vector) {
+                queue.handle_irq();
+            }
+        }
+
+        if masked {
+            self.reg().unmask_interrupt(vector);
+        }
+    }
+
+    /// Mask `vector` through INTMS. Only valid in pin and MSI mode.
+    pub fn mask_interrupt(&self, vector: u16) -> Result {
+        self.check_interrupt_mask(vector)?;
+        self.reg().mask_interrupt(vector);
         Ok(())
     }
 
+    /// Unmask `vector` through INTMC. Only valid in pin and MSI mode.
+    pub fn unmask_interrupt(&self, vector: u16) -> Result {
+        self.check_interrupt_mask(vector)?;
+        self.reg().unmask_interrupt(vector);
+        Ok(())
+    }
+
+    fn check_interrupt_mask(&self, vector: u16) -> Result {
+        match self.interrupt_mode {
+            InterruptMode::Pin | InterruptMode::Msi => {}
+            _ => {
+                return Err(Error::NotSupported(
+                    "INTMS/INTMC are only used in pin and MSI mode",
+                ))
+            }
+        }
+        // one bit per vector in a 32 bit register
+        if vector > 31 {
+            return Err(Error::InvalidParameter("interrupt vector above 31"));
+        }
+        Ok(())
+    }
+
+    /// Turn interrupt coalescing off or on for one vector.
+    pub fn set_interrupt_coalescing_disable(&mut self, vector: u16, disable: bool) -> Result {
+        let cmd = CommandSet::set_features(Feature::InterruptVectorConfiguration {
+            vector,
+            coalescing_disable: disable,
+        });
+        self.admin_queue.command_sync(cmd)?;
+        Ok(())
+    }
+
+    /// Current state of the controller as reported by CSTS.
+    pub fn controller_status(&self) -> ControllerStatus {
+        self.reg().controller_status()
+    }
+
     pub fn version(&self) -> (usize, usize, usize) {
         self.reg().version()
     }
@@ -257,10 +1075,63 @@ impl Nvme {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+// clamp a requested queue depth to what the controller supports
+fn queue_depth(requested: usize, max: usize) -> Result<usize> {
+    if requested < 2 {
+        return Err(Error::InvalidParameter("queue depth must be at least 2"));
+    }
+    if requested > max {
+        warn!("queue depth {requested} exceeds the controller limit, using {max}");
+        return Ok(max);
+    }
+    Ok(requested)
+}
+
+// the page size must be one CAP.MPSMIN..=MPSMAX allows, it is programmed
+// into CC.MPS and used for PRP entries and queue alignment
+fn check_page_size(reg: &NvmeReg, page_size: usize) -> Result {
+    let (min, max) = (reg.min_page_size(), reg.max_page_size());
+    if !page_size.is_power_of_two() || page_size < min || page_size > max {
+        warn!("page size {page_size:#x} not in supported range {min:#x}..={max:#x}");
+        return Err(Error::NotSupported("memory page size"));
+    }
+    Ok(())
+}
+
+impl Drop for Nvme {
+    fn drop(&mut self) {
+        if !self.shut_down {
+            if let Some(kind) = self.shutdown_on_drop {
+                if let Err(e) = self.shutdown(kind) {
+                    warn!("shutdown on drop failed: {e:?}");
+                }
+            }
+        }
+
+        // handles from `io_queue` may outlive the controller, make them
+        // fail instead of touching its registers
+        self.admin_queue.with(|q| q.set_deleted(true));
+        for io_queue in &self.io_queues {
+            io_queue.shared().with(|q| q.set_deleted(true));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Namespace {
     pub id: u32,
     pub lba_size: usize,
     pub lba_count: usize,
     pub metadata_size: usize,
 }
+
+impl Namespace {
+    pub fn new(id: u32, data: &NamespaceDataStructure) -> Self {
+        Self {
+            id,
+            lba_size: data.lba_size(),
+            lba_count: data.namespace_size as _,
+            metadata_size: data.metadata_size(),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use alloc::vec::Vec;
+use dma_api::{DVec, Direction};
+
+use crate::{
+    err::*,
+    queue::{CommandSet, DataPointer},
+};
+
+/// Physical Region Page entries describing one data buffer.
+///
+/// PRP1 always points at the first (possibly offset) page. PRP2 is either
+/// the second page, or the bus address of a PRP list when the buffer spans
+/// more than two pages. The last entry of a full list chains to the next one.
+pub struct PrpList {
+    pub prp1: u64,
+    pub prp2: u64,
+    // keep the lists alive until the command completes
+    _lists: Vec<DVec<u64>>,
+}
+
+impl PrpList {
+    pub fn new(bus_addr: u64, len: usize, page_size: usize) -> Result<Self> {
+        assert!(
+            page_size.is_power_of_two(),
+            "page size must be power of two"
+        );
+
+        let page_mask = page_size as u64 - 1;
+        let first_len = page_size - (bus_addr & page_mask) as usize;
+
+        let mut prp = PrpList {
+            prp1: bus_addr,
+            prp2: 0,
+            _lists: Vec::new(),
+        };
+
+        if len <= first_len {
+            return Ok(prp);
+        }
+
+        let first_page = (bus_addr & !page_mask) + page_size as u64;
+        let page_count = (len - first_len).div_ceil(page_size);
+
+        if page_count == 1 {
+            prp.prp2 = first_page;
+            return Ok(prp);
+        }
+
+        let entries_per_list = page_size / size_of::<u64>();
+
+        // every list but the last gives up its final entry to the chain pointer
+        let mut list_count = 1;
+        while list_count * (entries_per_list - 1) + 1 < page_count {
+            list_count += 1;
+        }
+
+        let mut lists = Vec::with_capacity(list_count);
+        for _ in 0..list_count {
+            let list = DVec::zeros(u64::MAX, entries_per_list, page_size, Direction::ToDevice)
+                .map_err(|_| Error::NoMemory)?;
+            lists.push(list);
+        }
+
+        let mut page = 0;
+        for i in 0..list_count {
+            let is_last = i + 1 == list_count;
+            let data_entries = if is_last {
+                page_count - page
+            } else {
+                entries_per_list - 1
+            };
+
+            for n in 0..data_entries {
+                let addr = first_page + ((page + n) * page_size) as u64;
+                lists[i].set(n, addr);
+            }
+            page += data_entries;
+
+            if !is_last {
+                let next = lists[i + 1].bus_addr();
+                lists[i].set(entries_per_list - 1, next);
+            }
+        }
+
+        prp.prp2 = lists[0].bus_addr();
+        prp._lists = lists;
+
+        Ok(prp)
+    }
+}
+
+impl DataPointer for PrpList {
+    fn fill(&self, cmd: &mut CommandSet) {
+        cmd.prp1 = self.prp1;
+        cmd.prp2 = self.prp2;
+    }
+}
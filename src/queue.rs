@@ -1,32 +1,20 @@
-use core::{
-    hint::spin_loop,
-    mem,
-    ptr::NonNull,
-    sync::atomic::{AtomicU32, Ordering},
-};
+use core::{hint::spin_loop, mem, ptr::NonNull, task::Waker, time::Duration};
 
-use dma_api::{DVec, Direction};
-use log::debug;
+use alloc::{collections::VecDeque, vec::Vec};
+use dma_api::{DSlice, DSliceMut, DVec, Direction};
+use log::{debug, warn};
 use tock_registers::register_bitfields;
 
 use crate::{
     command::{self, Feature},
     err::*,
+    prp::PrpList,
     registers::NvmeReg,
+    sgl::SglList,
+    time::Deadline,
 };
 
-static ID_FACTORY: AtomicU32 = AtomicU32::new(0);
-
-fn next_id() -> u32 {
-    if ID_FACTORY
-        .compare_exchange(0xFFFF, 0, Ordering::Relaxed, Ordering::Acquire)
-        .is_ok()
-    {
-        return 0;
-    }
-
-    ID_FACTORY.fetch_add(1, Ordering::Relaxed)
-}
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
 
 register_bitfields! [
     u32,
@@ -55,6 +43,8 @@ pub trait Submission {
     fn to_submission(self) -> NvmeSubmission;
 }
 
+/// One submission queue entry. Custom [`crate::Identify`] types fill in the
+/// command dwords, the driver sets the opcode and data pointer.
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Default)]
 //64B
@@ -74,18 +64,20 @@ pub struct CommandSet {
 }
 
 impl CommandSet {
-    pub fn cdw0_from_opcode(opcode: command::Opcode) -> u32 {
-        (CommandDword0::Opcode.val(opcode.as_u32()) + CommandDword0::CommandId.val(next_id())).value
+    pub(crate) fn cdw0_from_opcode(opcode: command::Opcode) -> u32 {
+        CommandDword0::Opcode.val(opcode.as_u32()).value
     }
 
-    pub fn set_features(feature: Feature) -> Self {
+    pub(crate) fn set_features(feature: Feature) -> Self {
         let cdw0 = Self::cdw0_from_opcode(command::Opcode::SET_FEATURES);
 
         let cdw10 = feature.to_cdw10();
-        let mut cdw11 = 0;
-        match feature {
-            Feature::NumberOfQueues { nsq, ncq } => cdw11 = nsq | ncq << 16,
-            Feature::InterruptVectorConfiguration {} => {}
+        let cdw11 = match feature {
+            Feature::NumberOfQueues { nsq, ncq } => nsq | ncq << 16,
+            Feature::InterruptVectorConfiguration {
+                vector,
+                coalescing_disable,
+            } => vector as u32 | (coalescing_disable as u32) << 16,
         };
 
         Self {
@@ -96,7 +88,16 @@ impl CommandSet {
         }
     }
 
-    pub fn create_io_completion_queue(
+    /// Abort the command `cid` of submission queue `sqid`.
+    pub(crate) fn abort(sqid: u16, cid: u16) -> Self {
+        Self {
+            cdw0: Self::cdw0_from_opcode(command::Opcode::ABORT),
+            cdw10: sqid as u32 | (cid as u32) << 16,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn create_io_completion_queue(
         qid: u32,
         size: u32,
         paddr: u64,
@@ -121,7 +122,7 @@ impl CommandSet {
         }
     }
 
-    pub fn create_io_submission_queue(
+    pub(crate) fn create_io_submission_queue(
         qid: u32,
         size: u32,
         paddr: u64,
@@ -145,41 +146,181 @@ impl CommandSet {
         }
     }
 
-    pub fn nvm_cmd_read(nsid: u32, paddr: u64, starting_lba: u64, blk_num: u16) -> Self {
+    /// Read `numd` dwords of log page `lid` starting at byte `offset`.
+    pub(crate) fn get_log_page(
+        lid: u8,
+        nsid: u32,
+        offset: u64,
+        numd: u32,
+        dptr: &impl DataPointer,
+    ) -> Self {
+        // NUMD is 0's based and split into NUMDL and NUMDU
+        let numd = numd - 1;
+        let mut cmd = Self {
+            cdw0: Self::cdw0_from_opcode(command::Opcode::GET_LOG_PAGE),
+            nsid,
+            cdw10: lid as u32 | (numd & 0xFFFF) << 16,
+            cdw11: numd >> 16,
+            cdw12: offset as u32,
+            cdw13: (offset >> 32) as u32,
+            ..Default::default()
+        };
+        dptr.fill(&mut cmd);
+        cmd
+    }
+
+    /// Send `numd` dwords of a firmware image, `offset` dwords into it.
+    pub(crate) fn firmware_image_download(numd: u32, offset: u32, dptr: &impl DataPointer) -> Self {
+        let mut cmd = Self {
+            cdw0: Self::cdw0_from_opcode(command::Opcode::FIRMWARE_IMAGE_DOWNLOAD),
+            // NUMD is 0's based
+            cdw10: numd - 1,
+            cdw11: offset,
+            ..Default::default()
+        };
+        dptr.fill(&mut cmd);
+        cmd
+    }
+
+    /// Commit the downloaded image to firmware slot `slot` with commit
+    /// action `action`.
+    pub(crate) fn firmware_commit(slot: u8, action: u8) -> Self {
+        Self {
+            cdw0: Self::cdw0_from_opcode(command::Opcode::FIRMWARE_COMMIT),
+            cdw10: (slot & 0b111) as u32 | ((action & 0b111) as u32) << 3,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn format_nvm(nsid: u32, cdw10: u32) -> Self {
+        Self {
+            cdw0: Self::cdw0_from_opcode(command::Opcode::FORMAT_NVM),
+            nsid,
+            cdw10,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn sanitize(cdw10: u32, cdw11: u32) -> Self {
+        Self {
+            cdw0: Self::cdw0_from_opcode(command::Opcode::SANITIZE),
+            cdw10,
+            cdw11,
+            ..Default::default()
+        }
+    }
+
+    /// Namespace Management create, `dptr` holds the namespace settings.
+    pub(crate) fn create_namespace(dptr: &impl DataPointer) -> Self {
+        let mut cmd = Self {
+            cdw0: Self::cdw0_from_opcode(command::Opcode::NAMESPACE_MANAGEMENT),
+            ..Default::default()
+        };
+        dptr.fill(&mut cmd);
+        cmd
+    }
+
+    /// Namespace Management delete of `nsid`.
+    pub(crate) fn delete_namespace(nsid: u32) -> Self {
+        Self {
+            cdw0: Self::cdw0_from_opcode(command::Opcode::NAMESPACE_MANAGEMENT),
+            nsid,
+            cdw10: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Namespace Attachment, `sel` 0 attaches `nsid` to the controllers in
+    /// the list, 1 detaches it.
+    pub(crate) fn namespace_attachment(nsid: u32, sel: u32, dptr: &impl DataPointer) -> Self {
+        let mut cmd = Self {
+            cdw0: Self::cdw0_from_opcode(command::Opcode::NAMESPACE_ATTACHMENT),
+            nsid,
+            cdw10: sel & 0xF,
+            ..Default::default()
+        };
+        dptr.fill(&mut cmd);
+        cmd
+    }
+
+    pub(crate) fn delete_io_submission_queue(qid: u32) -> Self {
+        Self {
+            cdw0: Self::cdw0_from_opcode(command::Opcode::DELETE_IO_SQ),
+            cdw10: qid & 0xffff,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn delete_io_completion_queue(qid: u32) -> Self {
+        Self {
+            cdw0: Self::cdw0_from_opcode(command::Opcode::DELETE_IO_CQ),
+            cdw10: qid & 0xffff,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn nvm_cmd_flush(nsid: u32) -> Self {
+        CommandSet {
+            nsid,
+            cdw0: Self::cdw0_from_opcode(command::Opcode::NVM_FLUSH),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn nvm_cmd_read(
+        nsid: u32,
+        dptr: &impl DataPointer,
+        starting_lba: u64,
+        blk_num: u32,
+    ) -> Self {
         let cdw0 = Self::cdw0_from_opcode(command::Opcode::NVM_READ);
         let low = (starting_lba & 0xFFFFFFFF) as u32;
         let high = (starting_lba >> 32) as u32;
-        let cdw12 = blk_num as u32;
+        // NLB is a 0's based value
+        let cdw12 = (blk_num - 1) & 0xFFFF;
 
-        CommandSet {
+        let mut cmd = CommandSet {
             nsid,
             cdw0,
-            prp1: paddr,
             cdw10: low,
             cdw11: high,
             cdw12,
             ..Default::default()
-        }
+        };
+        dptr.fill(&mut cmd);
+        cmd
     }
 
-    pub fn nvm_cmd_write(nsid: u32, paddr: u64, starting_lba: u64, blk_num: u16) -> Self {
+    pub(crate) fn nvm_cmd_write(
+        nsid: u32,
+        dptr: &impl DataPointer,
+        starting_lba: u64,
+        blk_num: u32,
+    ) -> Self {
         let cdw0 = Self::cdw0_from_opcode(command::Opcode::NVM_WRITE);
         let low = (starting_lba & 0xFFFFFFFF) as u32;
         let high = (starting_lba >> 32) as u32;
-        let cdw12 = blk_num as u32;
+        // NLB is a 0's based value
+        let cdw12 = (blk_num - 1) & 0xFFFF;
 
-        CommandSet {
+        let mut cmd = CommandSet {
             nsid,
             cdw0,
-            prp1: paddr,
             cdw10: low,
             cdw11: high,
             cdw12,
             ..Default::default()
-        }
+        };
+        dptr.fill(&mut cmd);
+        cmd
     }
 }
 
+/// Something that can be placed in the DPTR field of a command.
+pub trait DataPointer {
+    fn fill(&self, cmd: &mut CommandSet);
+}
+
 impl Submission for CommandSet {
     fn to_submission(self) -> NvmeSubmission {
         unsafe { mem::transmute(self) }
@@ -206,12 +347,88 @@ impl CompletionStatus {
     }
 
     fn is_success(&self) -> bool {
-        self.0 & (1 << 1) == 0
+        self.status_code() == 0 && self.status_code_type() == 0
+    }
+
+    pub fn status_code(&self) -> u8 {
+        (self.0 >> 1) as u8
+    }
+
+    pub fn status_code_type(&self) -> u8 {
+        ((self.0 >> 9) & 0b111) as u8
+    }
+
+    pub fn more(&self) -> bool {
+        self.0 & (1 << 14) > 0
     }
 
     pub fn do_not_retry(&self) -> bool {
         self.0 & (1 << 15) > 0
     }
+
+    fn to_error(self, sqid: u16, cid: u16, result: u32) -> CommandError {
+        CommandError {
+            status: Status::new(self.status_code_type(), self.status_code()),
+            sqid,
+            cid,
+            more: self.more(),
+            do_not_retry: self.do_not_retry(),
+            result,
+            log: None,
+        }
+    }
+}
+
+/// Token for a submitted command, matched against its [`Completion`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Request {
+    qid: u16,
+    cid: u16,
+}
+
+impl Request {
+    pub fn qid(&self) -> u16 {
+        self.qid
+    }
+
+    pub fn cid(&self) -> u16 {
+        self.cid
+    }
+}
+
+/// A finished command.
+#[derive(Debug, Clone, Copy)]
+pub struct Completion {
+    pub request: Request,
+    /// Dword 0 of the completion entry on success.
+    pub result: Result<u32>,
+}
+
+/// DMA memory a command references, kept alive until it completes.
+#[derive(Default)]
+#[allow(dead_code)]
+pub struct Payload {
+    pub prp: Option<PrpList>,
+    pub sgl: Option<SglList>,
+    pub read: Option<DSliceMut<'static, u8>>,
+    pub write: Option<DSlice<'static, u8>>,
+}
+
+struct Slot {
+    // kept to re-issue the command after a controller reset
+    command: CommandSet,
+    payload: Payload,
+    owner: Owner,
+}
+
+// who the completion of a command is handed to
+enum Owner {
+    /// Reported by [`NvmeQueue::poll_completions`].
+    Poller,
+    /// Claimed through [`NvmeQueue::take_completion`], waking the task if any.
+    Waiter(Option<Waker>),
+    /// Gave up on after a timeout, the completion is dropped.
+    Abandoned,
 }
 
 pub struct NvmeQueue {
@@ -219,8 +436,24 @@ pub struct NvmeQueue {
     pub sq: SubmitQueue,
     pub cq: CompleteQueue,
     pub reg: NonNull<NvmeReg>,
+    /// Interrupt vector of the completion queue, `None` when polled.
+    pub interrupt_vector: Option<u16>,
+    /// How long [`NvmeQueue::command_sync`] waits for a command.
+    pub timeout: Duration,
+    // indexed by command id
+    slots: Vec<Option<Slot>>,
+    next_cid: usize,
+    done: VecDeque<Completion>,
+    claimed: Vec<Completion>,
+    // the controller no longer knows the queue, or is gone altogether, so
+    // its registers must not be touched
+    deleted: bool,
 }
 
+// The queue owns its rings and in-flight buffers, and only touches the
+// doorbells of its own qid, so it may move between CPUs.
+unsafe impl Send for NvmeQueue {}
+
 impl NvmeQueue {
     pub fn new(
         qid: u32,
@@ -232,11 +465,22 @@ impl NvmeQueue {
         let submit_queue = SubmitQueue::new(sq, page_size)?;
         let complete_queue = CompleteQueue::new(cq, page_size)?;
 
+        // one entry stays empty so a full ring can be told from an empty one
+        let mut slots = Vec::new();
+        slots.resize_with(sq.min(cq) - 1, || None);
+
         Ok(NvmeQueue {
             sq: submit_queue,
             cq: complete_queue,
             qid,
             reg,
+            interrupt_vector: None,
+            timeout: DEFAULT_TIMEOUT,
+            slots,
+            next_cid: 0,
+            done: VecDeque::new(),
+            claimed: Vec::new(),
+            deleted: false,
         })
     }
 
@@ -244,28 +488,252 @@ impl NvmeQueue {
         unsafe { self.reg.as_ref() }
     }
 
-    fn submit_admin_data(&mut self, data: CommandSet) {
+    /// Put a command on the queue without waiting for it.
+    pub fn submit(&mut self, data: CommandSet, payload: Payload) -> Result<Request> {
+        self.submit_with_owner(data, payload, Owner::Poller)
+    }
+
+    /// Like [`NvmeQueue::submit`], but the completion is kept back from
+    /// [`NvmeQueue::poll_completions`] until claimed with
+    /// [`NvmeQueue::take_completion`].
+    pub fn submit_waited(&mut self, data: CommandSet, payload: Payload) -> Result<Request> {
+        self.submit_with_owner(data, payload, Owner::Waiter(None))
+    }
+
+    fn submit_with_owner(
+        &mut self,
+        mut data: CommandSet,
+        payload: Payload,
+        owner: Owner,
+    ) -> Result<Request> {
+        if self.deleted {
+            return Err(Error::QueueDeleted);
+        }
+
+        let len = self.slots.len();
+        let cid = (0..len)
+            .map(|i| (self.next_cid + i) % len)
+            .find(|&i| self.slots[i].is_none())
+            .ok_or(Error::QueueFull)?;
+        self.next_cid = (cid + 1) % len;
+
+        data.cdw0 = data.cdw0 & 0xFFFF | CommandDword0::CommandId.val(cid as _).value;
+        self.slots[cid] = Some(Slot {
+            command: data,
+            payload,
+            owner,
+        });
+
         let tail = self.sq.submit(data);
         self.reg().write_sq_y_tail_doolbell(self.qid as _, tail);
+
+        Ok(Request {
+            qid: self.qid as _,
+            cid: cid as _,
+        })
     }
 
-    pub fn command_sync(&mut self, data: CommandSet) -> Result<()> {
-        self.submit_admin_data(data);
-        let complete = self.cq.spin_for_complete();
+    // take one entry off the completion queue and hand it to its owner
+    fn reap(&mut self) -> bool {
+        if self.deleted {
+            return false;
+        }
+        let Some(complete) = self.cq.pop() else {
+            return false;
+        };
 
         self.reg()
             .write_cq_y_head_doolbell(self.qid as _, self.cq.head);
 
-        if complete.status.is_success() {
-            Ok(())
+        let result = if complete.status.is_success() {
+            Ok(complete.result as u32)
         } else {
             debug!(
                 "command failed: status {:#x}, result {:#x}",
                 complete.status.0, complete.result
             );
-            Err(Error::Unknown("send command failed"))
+            Err(Error::Command(complete.status.to_error(
+                self.qid as _,
+                complete.command_id,
+                complete.result as u32,
+            )))
+        };
+
+        let completion = Completion {
+            request: Request {
+                qid: self.qid as _,
+                cid: complete.command_id,
+            },
+            result,
+        };
+
+        let slot = self
+            .slots
+            .get_mut(complete.command_id as usize)
+            .and_then(|s| s.take());
+
+        let Some(Slot { payload, owner, .. }) = slot else {
+            debug!(
+                "queue {}: completion for unknown command {}",
+                self.qid, complete.command_id
+            );
+            return true;
+        };
+
+        if let Some(read) = &payload.read {
+            read.preper_read_all();
+        }
+
+        match owner {
+            Owner::Poller => self.done.push_back(completion),
+            Owner::Waiter(waker) => {
+                self.claimed.push(completion);
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+            Owner::Abandoned => debug!(
+                "queue {}: late completion for command {}",
+                self.qid, complete.command_id
+            ),
+        }
+
+        true
+    }
+
+    /// Move every posted completion to its owner.
+    pub fn drain(&mut self) {
+        while self.reap() {}
+    }
+
+    /// Drain the completion queue, returning every command finished since
+    /// the last call.
+    pub fn poll_completions(&mut self) -> Vec<Completion> {
+        self.drain();
+        self.done.drain(..).collect()
+    }
+
+    /// Drain the completion queue and take the completion of a command
+    /// submitted with [`NvmeQueue::submit_waited`], if it has finished.
+    pub fn take_completion(&mut self, request: Request) -> Option<Completion> {
+        self.drain();
+        let i = self.claimed.iter().position(|c| c.request == request)?;
+        Some(self.claimed.swap_remove(i))
+    }
+
+    /// Wake `waker` once the command of `request` completes.
+    pub fn register_waker(&mut self, request: Request, waker: &Waker) {
+        if let Some(Some(Slot {
+            owner: Owner::Waiter(w),
+            ..
+        })) = self.slots.get_mut(request.cid as usize)
+        {
+            match w {
+                Some(w) if w.will_wake(waker) => {}
+                _ => *w = Some(waker.clone()),
+            }
+        }
+    }
+
+    /// See [`NvmeReg::check_fatal`]. A deleted queue never completes
+    /// anything either.
+    pub fn check_fatal(&self) -> Result {
+        if self.deleted {
+            return Err(Error::QueueDeleted);
         }
+        self.reg().check_fatal()
+    }
+
+    /// Mark the queue deleted, or usable again once recreated. Commands on a
+    /// deleted queue fail with [`Error::QueueDeleted`].
+    pub fn set_deleted(&mut self, deleted: bool) {
+        self.deleted = deleted;
     }
+
+    /// Disable the controller so it stops all DMA, before the buffers of a
+    /// command that was given up on are released. A reset brings it back.
+    pub fn disable_controller(&self) {
+        if self.deleted {
+            return;
+        }
+        warn!("queue {}: disabling controller", self.qid);
+        if let Err(e) = self.reg().reset() {
+            warn!("queue {}: controller did not stop: {e:?}", self.qid);
+        }
+    }
+
+    /// Stop waiting for a command. Its slot and payload are held until the
+    /// controller completes it, the completion itself is discarded.
+    pub fn abandon(&mut self, request: Request) {
+        if let Some(Some(slot)) = self.slots.get_mut(request.cid as usize) {
+            slot.owner = Owner::Abandoned;
+        }
+    }
+
+    /// Forget everything the controller had posted, as after it was
+    /// disabled. Abandoned commands are dropped, the rest stay outstanding
+    /// for [`NvmeQueue::resubmit`].
+    pub fn reset_rings(&mut self) {
+        self.sq.tail = 0;
+        self.cq.clear();
+        for slot in &mut self.slots {
+            if matches!(
+                slot,
+                Some(Slot {
+                    owner: Owner::Abandoned,
+                    ..
+                })
+            ) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Post every outstanding command again under its old command id, once
+    /// the queue exists on the controller again.
+    pub fn resubmit(&mut self) {
+        let mut tail = None;
+        for slot in self.slots.iter().flatten() {
+            tail = Some(self.sq.submit(slot.command));
+        }
+        if let Some(tail) = tail {
+            debug!("queue {}: re-issued outstanding commands", self.qid);
+            self.reg().write_sq_y_tail_doolbell(self.qid as _, tail);
+        }
+    }
+
+    /// Submit a command and spin until it completes, returning dword 0 of
+    /// the completion.
+    pub fn command_sync(&mut self, data: CommandSet) -> Result<u32> {
+        let request = self.submit_waited(data, Payload::default())?;
+        let deadline = Deadline::after(self.timeout);
+
+        loop {
+            if let Some(c) = self.take_completion(request) {
+                return c.result;
+            }
+            if let Err(e) = self.check_fatal() {
+                self.abandon(request);
+                self.disable_controller();
+                return Err(e);
+            }
+            if deadline.expired() {
+                warn!("queue {}: command {} timed out", self.qid, request.cid);
+                self.abandon(request);
+                self.disable_controller();
+                return Err(Error::Timeout);
+            }
+            spin_loop();
+        }
+    }
+}
+
+// queue base addresses carry no offset, they must start on a memory page
+fn check_queue_base(bus_addr: u64, page_size: usize) -> Result {
+    if !bus_addr.is_multiple_of(page_size as u64) {
+        return Err(Error::Layout);
+    }
+    Ok(())
 }
 
 pub struct SubmitQueue {
@@ -275,7 +743,9 @@ pub struct SubmitQueue {
 
 impl SubmitQueue {
     fn new(queue_size: usize, page_size: usize) -> Result<Self> {
-        let queue = DVec::zeros(u64::MAX, queue_size, page_size, Direction::ToDevice).unwrap();
+        let queue = DVec::zeros(u64::MAX, queue_size, page_size, Direction::ToDevice)
+            .map_err(|_| Error::NoMemory)?;
+        check_queue_base(queue.bus_addr(), page_size)?;
         Ok(SubmitQueue { queue, tail: 0 })
     }
 
@@ -307,7 +777,9 @@ pub struct CompleteQueue {
 
 impl CompleteQueue {
     fn new(queue_size: usize, page_size: usize) -> Result<Self> {
-        let queue = DVec::zeros(u64::MAX, queue_size, page_size, Direction::FromDevice).unwrap();
+        let queue = DVec::zeros(u64::MAX, queue_size, page_size, Direction::FromDevice)
+            .map_err(|_| Error::NoMemory)?;
+        check_queue_base(queue.bus_addr(), page_size)?;
         Ok(CompleteQueue {
             queue,
             head: 0,
@@ -328,21 +800,28 @@ impl CompleteQueue {
         }
     }
 
-    fn spin_for_complete(&mut self) -> NvmeCompletion {
-        loop {
-            if let Some(e) = self.complete() {
-                let next_head = self.head + 1;
-                if next_head >= self.queue.len() as u32 {
-                    self.head = 0;
-                    self.phase = !self.phase;
-                } else {
-                    self.head = next_head;
-                }
+    // drop every posted entry and start over at phase 1
+    fn clear(&mut self) {
+        for i in 0..self.queue.len() {
+            self.queue.set(i, NvmeCompletion::default());
+        }
+        self.head = 0;
+        self.phase = false;
+    }
 
-                return e;
-            }
-            spin_loop();
+    // take the next completed entry, if any
+    fn pop(&mut self) -> Option<NvmeCompletion> {
+        let e = self.complete()?;
+
+        let next_head = self.head + 1;
+        if next_head >= self.queue.len() as u32 {
+            self.head = 0;
+            self.phase = !self.phase;
+        } else {
+            self.head = next_head;
         }
+
+        Some(e)
     }
 
     pub fn len(&self) -> usize {
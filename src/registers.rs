@@ -1,12 +1,19 @@
-use core::hint::spin_loop;
+use core::{hint::spin_loop, time::Duration};
 
 use log::debug;
 use tock_registers::{
-    interfaces::{Readable, Writeable},
+    interfaces::{ReadWriteable, Readable, Writeable},
     register_bitfields, register_structs,
     registers::{ReadOnly, ReadWrite, WriteOnly},
 };
 
+use crate::{err::*, time::Deadline};
+
+// CSTS.SHST once shutdown processing is done
+const SHUTDOWN_COMPLETE: u32 = 0b10;
+// "NVMe", starts an NVM subsystem reset when written to NSSR
+const NSSR_MAGIC: u32 = 0x4E564D65;
+
 register_structs! {
     pub(crate) NvmeReg {
         (0x000 => controller_capabilities: ReadOnly<u64, CAP::Register>),
@@ -129,15 +136,44 @@ register_bitfields! [
         // Command Sets Supported (CSS)
         CSS OFFSET(37) NUMBITS(8) [],
 
+        // Memory Page Size Minimum (MPSMIN)
+        MPSMIN OFFSET(48) NUMBITS(4) [],
+
+        // Memory Page Size Maximum (MPSMAX)
+        MPSMAX OFFSET(52) NUMBITS(4) [],
+
         // Controller Memory Buffer Supported
         CMBS OFFSET(57) NUMBITS(1) [],
     ],
 
 ];
 
-impl NvmeReg {
-    const QUEUE_BASE_MASK: u64 = !0xfff;
+/// Snapshot of the controller status register (CSTS).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControllerStatus {
+    /// RDY, the controller processes commands.
+    pub ready: bool,
+    /// CFS, the controller hit a fatal error and needs a reset.
+    pub fatal: bool,
+    /// SHST
+    pub shutdown: ShutdownStatus,
+    /// PP
+    pub processing_paused: bool,
+    /// NSSRO, an NVM subsystem reset occurred since the bit was last cleared.
+    pub subsystem_reset_occurred: bool,
+}
 
+/// Shutdown Status (CSTS.SHST).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownStatus {
+    /// No shutdown requested.
+    Normal,
+    Occurring,
+    Complete,
+    Reserved,
+}
+
+impl NvmeReg {
     pub fn version(&self) -> (usize, usize, usize) {
         let major = self.version.read(VS::Major);
         let minor = self.version.read(VS::Minor);
@@ -146,13 +182,43 @@ impl NvmeReg {
         (major as _, minor as _, tertiary as _)
     }
 
+    /// Minimum host memory page size supported by the controller, in bytes.
+    pub fn min_page_size(&self) -> usize {
+        1 << (12 + self.controller_capabilities.read(CAP::MPSMIN))
+    }
+
+    /// Largest I/O queue, in entries (CAP.MQES is 0's based).
+    pub fn max_queue_entries(&self) -> usize {
+        self.controller_capabilities.read(CAP::MQES) as usize + 1
+    }
+
+    /// CAP.CQR, I/O queues must be physically contiguous.
+    pub fn contiguous_queues_required(&self) -> bool {
+        self.controller_capabilities.is_set(CAP::CQR)
+    }
+
+    /// Maximum host memory page size supported by the controller, in bytes.
+    pub fn max_page_size(&self) -> usize {
+        1 << (12 + self.controller_capabilities.read(CAP::MPSMAX))
+    }
+
+    /// `vector` must be below 32.
+    pub fn mask_interrupt(&self, vector: u16) {
+        self.interrupt_mask_set.set(1 << vector);
+    }
+
+    /// `vector` must be below 32.
+    pub fn unmask_interrupt(&self, vector: u16) {
+        self.interrupt_mask_clear.set(1 << vector);
+    }
+
+    /// `addr` must be aligned to the memory page size.
     pub fn set_admin_submission_queue_base_address(&self, addr: u64) {
-        let addr = addr & Self::QUEUE_BASE_MASK;
         self.admin_submission_queue_base_address.set(addr);
     }
 
+    /// `addr` must be aligned to the memory page size.
     pub fn set_admin_completion_queue_base_address(&self, addr: u64) {
-        let addr = addr & Self::QUEUE_BASE_MASK;
         self.admin_completion_queue_base_address.set(addr);
     }
 
@@ -167,16 +233,45 @@ impl NvmeReg {
         );
     }
 
-    pub fn reset(&self) {
+    /// Worst case time to wait for CSTS.RDY to change, from CAP.TO.
+    pub fn ready_timeout(&self) -> Duration {
+        Duration::from_millis(500 * self.controller_capabilities.read(CAP::TO))
+    }
+
+    /// Notify the controller of a shutdown and wait for CSTS.SHST to report
+    /// it complete.
+    pub fn shutdown(&self, abrupt: bool, timeout: Duration) -> Result {
+        if abrupt {
+            self.controller_configuration
+                .modify(CC::ShutdownNotification::Abrupt);
+        } else {
+            self.controller_configuration
+                .modify(CC::ShutdownNotification::Normal);
+        }
+        debug!("Waiting for shutdown...");
+        spin_for_true(
+            || {
+                self.check_fatal()?;
+                Ok(self.controller_status.read(CSTS::SHST) == SHUTDOWN_COMPLETE)
+            },
+            Deadline::after(timeout),
+        )?;
+        debug!("Shutdown complete!");
+        Ok(())
+    }
+
+    pub fn reset(&self) -> Result {
         self.controller_configuration.write(CC::Enable::CLEAR);
         debug!("Waiting for reset...");
-        spin_for_true(|| !self.controller_status.is_set(CSTS::RDY));
-        debug!("Reset complete!")
+        self.wait_ready(false)?;
+        debug!("Reset complete!");
+        Ok(())
     }
 
-    pub fn setup_cc(&self, sqes: u32, cqes: u32) {
+    pub fn setup_cc(&self, sqes: u32, cqes: u32, page_size: usize) -> Result {
         self.controller_configuration.write(
             CC::Enable::SET
+                + CC::MemoryPageSize.val(memory_page_size(page_size))
                 + CC::IOCommandSetSelected::NVMCommandSet
                 + CC::ArbitrationMechanismSelected::RoundRobin
                 + CC::ShutdownNotification::None
@@ -184,20 +279,80 @@ impl NvmeReg {
                 + CC::IOCompletionQueueEntrySize.val(cqes),
         );
         debug!("Waiting for ready...");
-        spin_for_true(|| self.controller_status.is_set(CSTS::RDY));
+        self.wait_ready(true)?;
         debug!("Ready!");
+        Ok(())
     }
 
-    pub fn ready_for_read_controller_info(&self) {
+    pub fn ready_for_read_controller_info(&self, page_size: usize) -> Result {
         self.controller_configuration.write(
             CC::Enable::SET
+                + CC::MemoryPageSize.val(memory_page_size(page_size))
                 + CC::IOCommandSetSelected::AdminCommandSetOnly
                 + CC::ArbitrationMechanismSelected::RoundRobin
                 + CC::ShutdownNotification::None,
         );
         debug!("Waiting for ready...");
-        spin_for_true(|| self.controller_status.is_set(CSTS::RDY));
+        self.wait_ready(true)?;
         debug!("Ready!");
+        Ok(())
+    }
+
+    fn wait_ready(&self, ready: bool) -> Result {
+        let deadline = Deadline::after(self.ready_timeout());
+        spin_for_true(
+            || {
+                // a controller that failed to come up never sets RDY
+                if ready {
+                    self.check_fatal()?;
+                }
+                Ok(self.controller_status.is_set(CSTS::RDY) == ready)
+            },
+            deadline,
+        )
+    }
+
+    pub fn subsystem_reset_supported(&self) -> bool {
+        self.controller_capabilities.is_set(CAP::NSSRS)
+    }
+
+    /// Reset the whole NVM subsystem by writing "NVMe" to NSSR.
+    pub fn subsystem_reset(&self) {
+        self.nvm_subsystem_reset.set(NSSR_MAGIC);
+    }
+
+    /// Read and clear CSTS.NSSRO.
+    pub fn take_subsystem_reset_occurred(&self) -> bool {
+        let occurred = self.controller_status.is_set(CSTS::NSSRO);
+        if occurred {
+            // write 1 to clear
+            self.controller_status.write(CSTS::NSSRO::SET);
+        }
+        occurred
+    }
+
+    /// Fails with [`Error::ControllerFatal`] if CSTS.CFS is set.
+    pub fn check_fatal(&self) -> Result {
+        if self.controller_status.is_set(CSTS::CFS) {
+            return Err(Error::ControllerFatal);
+        }
+        Ok(())
+    }
+
+    pub fn controller_status(&self) -> ControllerStatus {
+        let csts = self.controller_status.extract();
+        ControllerStatus {
+            ready: csts.is_set(CSTS::RDY),
+            fatal: csts.is_set(CSTS::CFS),
+            shutdown: match csts.read(CSTS::SHST) {
+                0b00 => ShutdownStatus::Normal,
+                0b01 => ShutdownStatus::Occurring,
+                0b10 => ShutdownStatus::Complete,
+                _ => ShutdownStatus::Reserved,
+            },
+            processing_paused: csts.is_set(CSTS::PP),
+            subsystem_reset_occurred: csts.is_set(CSTS::NSSRO),
+        }
     }
 
     // write submission queue doorbell to notify nvme device
@@ -221,11 +376,20 @@ impl NvmeReg {
     }
 }
 
-fn spin_for_true<F>(f: F)
+// CC.MPS encoding of a page size in bytes
+fn memory_page_size(page_size: usize) -> u32 {
+    page_size.trailing_zeros() - 12
+}
+
+fn spin_for_true<F>(f: F, deadline: Deadline) -> Result
 where
-    F: Fn() -> bool,
+    F: Fn() -> Result<bool>,
 {
-    while !f() {
+    while !f()? {
+        if deadline.expired() {
+            return Err(Error::Timeout);
+        }
         spin_loop();
     }
+    Ok(())
 }
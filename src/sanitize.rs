@@ -0,0 +1,74 @@
+/// What a sanitize does to the media (SANACT).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SanitizeAction {
+    /// Low level block erase of the media, SANICAP bit 1.
+    BlockErase,
+    /// Change the media encryption keys, SANICAP bit 0.
+    CryptoErase,
+    /// Write `pattern` over the media `passes` times, 1 to 16, SANICAP
+    /// bit 2. With `invert` the pattern is inverted between passes.
+    Overwrite {
+        pattern: u32,
+        passes: u8,
+        invert: bool,
+    },
+}
+
+impl SanitizeAction {
+    pub(crate) fn sanact(&self) -> u32 {
+        match self {
+            Self::BlockErase => 0b010,
+            Self::Overwrite { .. } => 0b011,
+            Self::CryptoErase => 0b100,
+        }
+    }
+
+    // SANICAP bit of the action
+    pub(crate) fn capability(&self) -> u32 {
+        match self {
+            Self::CryptoErase => 1,
+            Self::BlockErase => 1 << 1,
+            Self::Overwrite { .. } => 1 << 2,
+        }
+    }
+}
+
+/// Settings for [`crate::Nvme::sanitize`].
+#[derive(Debug, Clone, Copy)]
+pub struct Sanitize {
+    pub action: SanitizeAction,
+    /// Leave the media allocated afterwards (NDAS) instead of deallocating
+    /// it.
+    pub no_deallocate: bool,
+    /// A failed sanitize may be left with a plain exit command (AUSE),
+    /// otherwise only a successful sanitize ends failure mode.
+    pub allow_unrestricted_exit: bool,
+}
+
+impl Sanitize {
+    pub fn new(action: SanitizeAction) -> Self {
+        Self {
+            action,
+            no_deallocate: false,
+            allow_unrestricted_exit: false,
+        }
+    }
+
+    pub(crate) fn cdw10(&self) -> u32 {
+        let mut cdw10 = self.action.sanact()
+            | (self.allow_unrestricted_exit as u32) << 3
+            | (self.no_deallocate as u32) << 9;
+        if let SanitizeAction::Overwrite { passes, invert, .. } = self.action {
+            // OWPASS 0 means 16 passes
+            cdw10 |= (passes as u32 & 0xF) << 4 | (invert as u32) << 8;
+        }
+        cdw10
+    }
+
+    pub(crate) fn cdw11(&self) -> u32 {
+        match self.action {
+            SanitizeAction::Overwrite { pattern, .. } => pattern,
+            _ => 0,
+        }
+    }
+}
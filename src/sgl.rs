@@ -0,0 +1,155 @@
+use alloc::vec::Vec;
+use dma_api::{DVec, Direction};
+
+use crate::{
+    err::*,
+    queue::{CommandDword0, CommandSet, DataPointer},
+};
+
+/// One 16 byte SGL descriptor.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SglDescriptor {
+    pub address: u64,
+    pub length: u32,
+    pub rsv: [u8; 3],
+    /// Descriptor type in bits 7:4, sub type in bits 3:0.
+    pub identifier: u8,
+}
+
+impl SglDescriptor {
+    pub const DATA_BLOCK: u8 = 0x0;
+    pub const BIT_BUCKET: u8 = 0x1;
+    pub const SEGMENT: u8 = 0x2;
+    pub const LAST_SEGMENT: u8 = 0x3;
+
+    fn new(descriptor_type: u8, address: u64, length: u32) -> Self {
+        Self {
+            address,
+            length,
+            rsv: [0; 3],
+            identifier: descriptor_type << 4,
+        }
+    }
+
+    pub fn data_block(address: u64, length: u32) -> Self {
+        Self::new(Self::DATA_BLOCK, address, length)
+    }
+
+    pub fn bit_bucket(length: u32) -> Self {
+        Self::new(Self::BIT_BUCKET, 0, length)
+    }
+
+    /// Points at a segment holding `count` data descriptors, followed by
+    /// the descriptor of the next segment unless it is the `last` one.
+    fn segment(address: u64, count: usize, last: bool) -> Self {
+        // the length covers the whole segment, chain descriptor included
+        let (descriptor_type, count) = if last {
+            (Self::LAST_SEGMENT, count)
+        } else {
+            (Self::SEGMENT, count + 1)
+        };
+        Self::new(
+            descriptor_type,
+            address,
+            (count * size_of::<SglDescriptor>()) as u32,
+        )
+    }
+}
+
+/// SGL support reported by the SGLS field of Identify Controller.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SglSupport(pub u32);
+
+impl SglSupport {
+    /// SGLs are supported for NVM commands.
+    pub fn is_supported(&self) -> bool {
+        self.0 & 0b11 != 0
+    }
+
+    /// Data blocks must be dword aligned and dword granular.
+    pub fn dword_aligned(&self) -> bool {
+        self.0 & 0b11 == 0b10
+    }
+
+    /// The SGL Bit Bucket descriptor is supported.
+    pub fn bit_bucket(&self) -> bool {
+        self.0 & (1 << 16) != 0
+    }
+}
+
+/// Scatter gather list describing one transfer.
+///
+/// A single data block is placed directly in the command. Otherwise the
+/// command points at a chain of page sized segments, each full segment
+/// ending in a descriptor for the next one.
+pub struct SglList {
+    pub descriptor: SglDescriptor,
+    // keep the segments alive until the command completes
+    _segments: Vec<DVec<SglDescriptor>>,
+}
+
+impl SglList {
+    pub fn new(descriptors: &[SglDescriptor], page_size: usize) -> Result<Self> {
+        assert!(!descriptors.is_empty(), "empty sgl");
+
+        if descriptors.len() == 1 {
+            return Ok(Self {
+                descriptor: descriptors[0],
+                _segments: Vec::new(),
+            });
+        }
+
+        let per_segment = page_size / size_of::<SglDescriptor>();
+
+        // every segment but the last gives up its final entry to the chain descriptor
+        let mut counts = Vec::new();
+        let mut remain = descriptors.len();
+        while remain > per_segment {
+            counts.push(per_segment - 1);
+            remain -= per_segment - 1;
+        }
+        counts.push(remain);
+
+        let mut segments = Vec::with_capacity(counts.len());
+        for _ in 0..counts.len() {
+            let segment = DVec::zeros(u64::MAX, per_segment, page_size, Direction::ToDevice)
+                .map_err(|_| Error::NoMemory)?;
+            segments.push(segment);
+        }
+
+        let mut descriptors = descriptors.iter();
+        for i in 0..segments.len() {
+            for n in 0..counts[i] {
+                segments[i].set(n, *descriptors.next().unwrap());
+            }
+
+            if let Some(&next_count) = counts.get(i + 1) {
+                let next = SglDescriptor::segment(
+                    segments[i + 1].bus_addr(),
+                    next_count,
+                    i + 2 == counts.len(),
+                );
+                segments[i].set(counts[i], next);
+            }
+        }
+
+        Ok(Self {
+            descriptor: SglDescriptor::segment(
+                segments[0].bus_addr(),
+                counts[0],
+                counts.len() == 1,
+            ),
+            _segments: segments,
+        })
+    }
+}
+
+impl DataPointer for SglList {
+    fn fill(&self, cmd: &mut CommandSet) {
+        let d = self.descriptor;
+        cmd.prp1 = d.address;
+        cmd.prp2 = d.length as u64 | (d.identifier as u64) << 56;
+        cmd.cdw0 |= CommandDword0::PSDT::SGLSignal.value;
+    }
+}
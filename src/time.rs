@@ -0,0 +1,65 @@
+use core::time::Duration;
+
+use log::warn;
+use spin::Once;
+
+/// Monotonic clock used to bound waits on the controller.
+pub trait TimeSource: Sync {
+    /// Time elapsed since an arbitrary fixed point.
+    fn now(&self) -> Duration;
+}
+
+impl core::fmt::Debug for dyn TimeSource {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("TimeSource").field(&self.now()).finish()
+    }
+}
+
+static TIME_SOURCE: Once<&'static dyn TimeSource> = Once::new();
+static UNSET_WARNED: Once = Once::new();
+
+/// Install the clock used for controller and command timeouts, also done
+/// through [`crate::Config::time_source`].
+///
+/// Until one is installed every wait is unbounded. Only the first call has
+/// an effect.
+pub fn set_time_source(source: &'static dyn TimeSource) {
+    TIME_SOURCE.call_once(|| source);
+}
+
+/// Whether a time source is installed, so deadlines expire.
+pub(crate) fn is_set() -> bool {
+    TIME_SOURCE.get().is_some()
+}
+
+/// Warn, once, that no time source is installed and waits are unbounded.
+pub(crate) fn warn_if_unset() {
+    if !is_set() {
+        UNSET_WARNED.call_once(|| {
+            warn!("no time source installed, a hung controller blocks forever");
+        });
+    }
+}
+
+fn now() -> Option<Duration> {
+    TIME_SOURCE.get().map(|s| s.now())
+}
+
+/// Point in time after which a wait gives up.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline(Option<Duration>);
+
+impl Deadline {
+    /// Never expires if no time source is installed, or if `timeout` is too
+    /// long to represent, e.g. [`Duration::MAX`].
+    pub fn after(timeout: Duration) -> Self {
+        Self(now().and_then(|now| now.checked_add(timeout)))
+    }
+
+    pub fn expired(&self) -> bool {
+        match (self.0, now()) {
+            (Some(deadline), Some(now)) => now >= deadline,
+            _ => false,
+        }
+    }
+}
@@ -78,7 +78,391 @@ mod tests {
         println!("test passed!");
     }
 
+    #[test]
+    fn test_large_transfer() {
+        let mut nvme = get_nvme();
+        let ns = nvme.namespace_list().unwrap()[0];
+
+        // 128 KiB crosses many pages and needs a PRP list
+        let len = 128 * 1024;
+        let block_start = 1024;
+
+        let write_buff: alloc::vec::Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        nvme.block_write_sync(&ns, block_start, &write_buff)
+            .unwrap();
+
+        let mut read_buff = alloc::vec![0u8; len];
+        nvme.block_read_sync(&ns, block_start, &mut read_buff)
+            .unwrap();
+
+        assert_eq!(write_buff, read_buff);
+
+        println!("large transfer test passed!");
+    }
+
+    #[test]
+    fn test_vectored_sgl() {
+        let mut nvme = get_nvme();
+        let ns = nvme.namespace_list().unwrap()[0];
+
+        // more descriptors than fit in one segment, so the list gets chained
+        let seg_len = 64;
+        let count = 320;
+        let block_start = 1536;
+
+        let write_buffs: alloc::vec::Vec<_> = (0..count)
+            .map(|i| alloc::vec![i as u8 + 1; seg_len])
+            .collect();
+        let write_refs: alloc::vec::Vec<&[u8]> = write_buffs.iter().map(|b| b.as_slice()).collect();
+        match nvme.block_write_vectored_sync(&ns, block_start, &write_refs) {
+            Err(err::Error::NotSupported(_)) => {
+                println!("SGL not supported, skip");
+                return;
+            }
+            r => r.unwrap(),
+        }
+
+        // the first segment goes to the bit bucket
+        let mut read_buffs: alloc::vec::Vec<_> =
+            (1..count).map(|_| alloc::vec![0u8; seg_len]).collect();
+        let mut segments = alloc::vec![ReadSegment::Skip(seg_len)];
+        segments.extend(
+            read_buffs
+                .iter_mut()
+                .map(|b| ReadSegment::Buffer(b.as_mut_slice())),
+        );
+        match nvme.block_read_vectored_sync(&ns, block_start, &mut segments) {
+            Err(err::Error::NotSupported(_)) => {
+                println!("SGL bit bucket not supported, skip");
+                return;
+            }
+            r => r.unwrap(),
+        }
+        drop(segments);
+
+        assert_eq!(write_buffs[1..], read_buffs[..]);
+
+        println!("vectored sgl test passed!");
+    }
+
+    #[test]
+    fn test_submit_and_poll() {
+        let mut nvme = get_nvme();
+        let ns = nvme.namespace_list().unwrap()[0];
+
+        let count = 8;
+        let block_start = 2048;
+
+        let write_buffs: alloc::vec::Vec<_> = (0..count)
+            .map(|i| alloc::vec![i as u8 + 1; ns.lba_size])
+            .collect();
+        let mut read_buffs: alloc::vec::Vec<_> =
+            (0..count).map(|_| alloc::vec![0u8; ns.lba_size]).collect();
+
+        let mut pending = alloc::vec::Vec::new();
+        for (i, buff) in write_buffs.iter().enumerate() {
+            let req = unsafe { nvme.submit_write(&ns, block_start + i as u64, buff) }.unwrap();
+            pending.push(req);
+        }
+        wait_all(&mut nvme, &mut pending);
+
+        for (i, buff) in read_buffs.iter_mut().enumerate() {
+            let req = unsafe { nvme.submit_read(&ns, block_start + i as u64, buff) }.unwrap();
+            pending.push(req);
+        }
+        wait_all(&mut nvme, &mut pending);
+
+        assert_eq!(write_buffs, read_buffs);
+
+        println!("submit and poll test passed!");
+    }
+
+    #[test]
+    fn test_async_io() {
+        let mut nvme = get_nvme();
+        let ns = nvme.namespace_list().unwrap()[0];
+
+        let block_start = 4096;
+        let write_buff = alloc::vec![0x5au8; ns.lba_size * 4];
+        let mut read_buff = alloc::vec![0u8; ns.lba_size * 4];
+
+        // the futures are driven to completion right here
+        block_on(unsafe { nvme.write(&ns, block_start, &write_buff) }).unwrap();
+        block_on(nvme.flush(&ns)).unwrap();
+        block_on(unsafe { nvme.read(&ns, block_start, &mut read_buff) }).unwrap();
+
+        assert_eq!(write_buff, read_buff);
+
+        println!("async io test passed!");
+    }
+
+    #[test]
+    fn test_multiple_queues() {
+        let mut nvme = get_nvme_with(2);
+        assert_eq!(nvme.io_queue_count(), 2);
+        let ns = nvme.namespace_list().unwrap()[0];
+
+        for i in 0..2 {
+            let io_queue = nvme.io_queue(i).unwrap();
+            let block_start = 7168 + i as u64;
+            let write_buff = alloc::vec![i as u8 + 0x10; ns.lba_size];
+            let mut read_buff = alloc::vec![0u8; ns.lba_size];
+
+            io_queue
+                .block_write_sync(&ns, block_start, &write_buff)
+                .unwrap();
+            io_queue
+                .block_read_sync(&ns, block_start, &mut read_buff)
+                .unwrap();
+            assert_eq!(write_buff, read_buff);
+        }
+
+        println!("multiple queues test passed!");
+    }
+
+    #[test]
+    fn test_status_decoding() {
+        let mut nvme = get_nvme();
+        let ns = nvme.namespace_list().unwrap()[0];
+        let mut buff = alloc::vec![0u8; ns.lba_size];
+
+        // one past the last block
+        let request = unsafe { nvme.submit_read(&ns, ns.lba_count as u64, &mut buff) }.unwrap();
+        let result = loop {
+            if let Some(c) = nvme
+                .poll_completions()
+                .into_iter()
+                .find(|c| c.request == request)
+            {
+                break c.result;
+            }
+        };
+
+        match result {
+            Err(err::Error::Command(err::CommandError {
+                status: err::Status::Generic(err::GenericStatus::LbaOutOfRange),
+                sqid,
+                cid,
+                ..
+            })) => {
+                assert_eq!(sqid, request.qid());
+                assert_eq!(cid, request.cid());
+            }
+            r => panic!("unexpected result: {r:?}"),
+        }
+
+        println!("status decoding test passed!");
+    }
+
+    #[test]
+    fn test_reset_controller() {
+        let mut nvme = get_nvme();
+        let ns = nvme.namespace_list().unwrap()[0];
+        let io_queue = nvme.io_queue(0).unwrap();
+
+        let block_start = 6144;
+        let write_buff = alloc::vec![0xa5u8; ns.lba_size];
+        let mut read_buff = alloc::vec![0u8; ns.lba_size];
+
+        io_queue
+            .block_write_sync(&ns, block_start, &write_buff)
+            .unwrap();
+
+        let namespaces = nvme.reset_controller().unwrap();
+        assert!(namespaces.contains(&ns));
+
+        io_queue
+            .block_read_sync(&ns, block_start, &mut read_buff)
+            .unwrap();
+        assert_eq!(write_buff, read_buff);
+
+        println!("reset controller test passed!");
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let mut nvme = get_nvme();
+        let ns = nvme.namespace_list().unwrap()[0];
+        let io_queue = nvme.io_queue(0).unwrap();
+        let mut buff = alloc::vec![0u8; ns.lba_size];
+
+        nvme.shutdown(ShutdownKind::Normal).unwrap();
+
+        let r = io_queue.block_read_sync(&ns, 0, &mut buff);
+        assert!(matches!(r, Err(err::Error::QueueDeleted)));
+
+        // the controller comes back through a reset
+        let namespaces = nvme.reset_controller().unwrap();
+        assert!(!namespaces.is_empty());
+        io_queue.block_read_sync(&ns, 0, &mut buff).unwrap();
+
+        println!("shutdown test passed!");
+    }
+
+    #[test]
+    fn test_format_nvm() {
+        let mut nvme = get_nvme();
+        let ns = nvme.namespace_list().unwrap()[0];
+        let data = nvme.identify_namespace(ns.id).unwrap().unwrap();
+
+        let namespaces = nvme
+            .format_nvm(ns.id, FormatNvm::new(data.formatted_lba_index as u8))
+            .unwrap();
+        let formatted = namespaces.iter().find(|n| n.id == ns.id).unwrap();
+        assert_eq!(formatted.lba_size, data.lba_size());
+
+        println!("format nvm test passed!");
+    }
+
+    #[test]
+    fn test_identify_controller() {
+        let mut nvme = get_nvme();
+        let controller = nvme.identify_controller().unwrap();
+        println!(
+            "controller: {} {} fw {}",
+            controller.model_number, controller.serial_number, controller.firmware_revision
+        );
+        assert!(!controller.power_states.is_empty());
+
+        println!("identify controller test passed!");
+    }
+
+    #[test]
+    fn test_identify_namespace() {
+        let mut nvme = get_nvme();
+        for ns in nvme.namespace_list().unwrap() {
+            let data = nvme.identify_namespace(ns.id).unwrap().unwrap();
+            assert_eq!(Namespace::new(ns.id, &data), ns);
+            println!("lba formats: {:?}", data.lba_formats);
+        }
+
+        println!("identify namespace test passed!");
+    }
+
+    #[test]
+    fn test_namespace_identifiers() {
+        let mut nvme = get_nvme();
+        for ns in nvme.namespace_list().unwrap() {
+            let ids = nvme.namespace_identifiers(ns.id).unwrap();
+            println!("identifiers: {ids:?}");
+            if let Some(uuid) = ids.uuid {
+                assert_eq!(nvme.find_namespace_by_uuid(&uuid).unwrap(), Some(ns));
+            }
+        }
+
+        println!("namespace identifiers test passed!");
+    }
+
+    #[test]
+    fn test_allocated_namespace_list() {
+        let mut nvme = get_nvme();
+        let allocated = nvme
+            .get_identfy(IdentifyAllocatedNamespaceList::new(0))
+            .unwrap();
+        for ns in nvme.namespace_list().unwrap() {
+            assert!(allocated.contains(&ns.id));
+        }
+
+        println!("allocated namespace list test passed!");
+    }
+
+    #[test]
+    fn test_smart_log() {
+        let mut nvme = get_nvme();
+        let smart = nvme.smart_log(NSID_ALL).unwrap();
+        println!("smart: {smart:?}");
+        assert!(smart.composite_temperature > 0);
+
+        println!("smart log test passed!");
+    }
+
+    #[test]
+    fn test_error_log() {
+        let mut nvme = get_nvme();
+        let errors = nvme.error_log().unwrap();
+        println!("error log: {} entries", errors.len());
+
+        println!("error log test passed!");
+    }
+
+    #[test]
+    fn test_firmware_slot_log() {
+        let mut nvme = get_nvme();
+        let slots = nvme.firmware_slot_log().unwrap();
+        println!("firmware slots: {slots:?}");
+        assert!(slots.revisions[slots.active_slot as usize - 1].is_some());
+
+        println!("firmware slot log test passed!");
+    }
+
+    #[test]
+    fn test_sanitize_capability() {
+        let mut nvme = get_nvme();
+        let controller = nvme.identify_controller().unwrap();
+        if controller.sanicap & 0b111 == 0 {
+            let r = nvme.sanitize(Sanitize::new(SanitizeAction::BlockErase));
+            assert!(matches!(r, Err(err::Error::NotSupported(_))));
+        }
+
+        println!("sanitize capability test passed!");
+    }
+
+    #[test]
+    fn test_namespace_management() {
+        let mut nvme = get_nvme();
+        let controller = nvme.identify_controller().unwrap();
+        if controller.oacs & (1 << 3) == 0 {
+            let r = nvme.create_namespace(CreateNamespace::new(1024, 0));
+            assert!(matches!(r, Err(err::Error::NotSupported(_))));
+            return;
+        }
+
+        let nsid = nvme
+            .create_namespace(CreateNamespace::new(1024, 0))
+            .unwrap();
+        let controllers = [controller.controller_id];
+
+        let namespaces = nvme.attach_namespace(nsid, &controllers).unwrap();
+        assert!(namespaces.iter().any(|ns| ns.id == nsid));
+        assert!(nvme
+            .namespace_list()
+            .unwrap()
+            .iter()
+            .any(|ns| ns.id == nsid));
+
+        let namespaces = nvme.detach_namespace(nsid, &controllers).unwrap();
+        assert!(!namespaces.iter().any(|ns| ns.id == nsid));
+
+        let namespaces = nvme.delete_namespace(nsid).unwrap();
+        assert!(!namespaces.iter().any(|ns| ns.id == nsid));
+
+        println!("namespace management test passed!");
+    }
+
+    fn block_on<F: core::future::Future>(f: F) -> F::Output {
+        let mut f = core::pin::pin!(f);
+        let mut cx = core::task::Context::from_waker(core::task::Waker::noop());
+        loop {
+            if let core::task::Poll::Ready(out) = f.as_mut().poll(&mut cx) {
+                return out;
+            }
+        }
+    }
+
+    fn wait_all(nvme: &mut Nvme, pending: &mut alloc::vec::Vec<Request>) {
+        while !pending.is_empty() {
+            for c in nvme.poll_completions() {
+                c.result.unwrap();
+                pending.retain(|r| *r != c.request);
+            }
+        }
+    }
+
     fn get_nvme() -> Nvme {
+        get_nvme_with(1)
+    }
+
+    fn get_nvme_with(io_queue_pair_count: usize) -> Nvme {
         let PlatformInfoKind::DeviceTree(fdt) = &global_val().platform_info;
         let fdt = fdt.get();
         let pcie = fdt
@@ -160,7 +544,8 @@ mod tests {
                     addr,
                     Config {
                         page_size,
-                        io_queue_pair_count: 1,
+                        io_queue_pair_count,
+                        ..Default::default()
                     },
                 )
                 .inspect_err(|e| error!("{e:?}"))